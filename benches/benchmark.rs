@@ -8,7 +8,6 @@ use criterion::profiler::Profiler;
 
 use twentysixtyfour::{algorithm, simulate};
 use std::path::Path;
-use std::fs::File;
 
 pub struct FlameProfiler;
 impl Profiler for FlameProfiler {
@@ -28,11 +27,12 @@ fn profiled() -> Criterion {
 }
 
 fn simulate_lookahead() -> simulate::BulkRunResult {
-    simulate::bulk(|mut player, board| algorithm::naive_lookahead(&mut player, board, 2, algorithm::ScoreFunction::FreeSpaceWithSortedness), 1)
+    let strategy = algorithm::NaiveLookahead{moves: 2, score_fn: algorithm::ScoreFunction::FreeSpaceWithSortedness};
+    simulate::bulk(&strategy, &simulate::SimulationConfig{runs: 1, threads: 1, ..Default::default()}).unwrap()
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("lookahead3", |b| b.iter(|| simulate_lookahead()));
+    c.bench_function("lookahead3", |b| b.iter(simulate_lookahead));
      // Dump the profile report to disk
     //flame::dump_html(&mut File::create("flame-graph.html").unwrap()).unwrap();
 }
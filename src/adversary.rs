@@ -0,0 +1,199 @@
+use super::gameplay::*;
+use super::merge::MergeRule;
+use super::threes;
+use super::algorithm::{ScoreFunction, score_function};
+
+// Something which picks where new tiles go, and what they are, instead of
+// spawning them at random. Playing strategies against one shows how they do
+// in the worst case rather than on average. Like strategies, adversaries are
+// cloneable and Send so that each game can have its own copy.
+pub trait Adversary: AdversaryClone + Send + Sync {
+    // Short name, used to tell results against different adversaries apart
+    fn name(&self) -> String;
+
+    // Choose a new tile for the board: one of cells, which is never empty,
+    // with one of the values of rules
+    fn choose_spawn(&mut self, board: &Board, cells: &[usize], rules: &SpawnRules) -> Spawn;
+}
+
+pub trait AdversaryClone {
+    fn clone_box(&self) -> Box<dyn Adversary>;
+}
+
+impl<T: 'static + Adversary + Clone> AdversaryClone for T {
+    fn clone_box(&self) -> Box<dyn Adversary> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Adversary> {
+    fn clone(&self) -> Box<dyn Adversary> {
+        self.clone_box()
+    }
+}
+
+impl std::fmt::Debug for Box<dyn Adversary> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Adversary({})", self.name())
+    }
+}
+
+// Places each tile where it hurts the player most by the player's own
+// heuristic: for every cell and value it looks at the best the player can do
+// with their next move, and picks the tile which makes that worst. A tile
+// which leaves no move at all ends the game, so is always picked.
+#[derive(Clone)]
+pub struct WorstCase {
+    pub score_fn: ScoreFunction,
+}
+
+impl Adversary for WorstCase {
+    fn name(&self) -> String {
+        format!("worst_case{}", self.score_fn.name_suffix())
+    }
+
+    fn choose_spawn(&mut self, board: &Board, cells: &[usize], rules: &SpawnRules) -> Spawn {
+        let score_fn = score_function(self.score_fn);
+        let mut worst = None;
+        let mut worst_rank = i32::MAX;
+        let mut spawned = board.clone();
+        for cell in cells {
+            for (value, _) in rules.values.iter().filter(|(_, weight)| *weight > 0) {
+                spawned.set(*cell, *value);
+                let rank = best_reply(&spawned, score_fn);
+                if rank < worst_rank {
+                    worst_rank = rank;
+                    worst = Some(Spawn{cell: *cell, value: *value});
+                }
+            }
+            spawned.set(*cell, board.values()[*cell]);
+        }
+        worst.expect("no cells to spawn in")
+    }
+}
+
+// The highest ranked position the player can reach by sliding, or i32::MIN
+// if they can't move
+fn best_reply(board: &Board, score_fn: fn(&Board) -> i32) -> i32 {
+    let mut best = i32::MIN;
+    for dir in &[MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right] {
+        let mut after = board.clone();
+        if slide_inplace(&mut after, *dir).is_ok() {
+            best = best.max(score_fn(&after));
+        }
+    }
+    best
+}
+
+// Like play_inplace, but with the adversary choosing the new tiles. As with
+// random spawns, rules.tiles_per_move tiles go in empty cells, or as many as
+// fit; in Threes one tile enters at the far end of a line which moved.
+pub fn play_adversarial(b: &mut Board, dir: MoveDir, adversary: &mut dyn Adversary, rules: &SpawnRules) -> Result<MoveResult, GameError> {
+    let before = if b.merge == MergeRule::Threes { Some(b.clone()) } else { None };
+    let score = slide_inplace(b, dir)?;
+    let mut spawned = Vec::with_capacity(rules.tiles_per_move);
+    let count = if before.is_some() { 1 } else { rules.tiles_per_move };
+    for _ in 0..count {
+        let cells: Vec<usize> = match &before {
+            Some(before) => threes::entry_cells(before, b, dir),
+            None => (0..b.values().len()).filter(|cell| b.values()[*cell] == 0).collect(),
+        };
+        if cells.is_empty() {
+            break;
+        }
+        let spawn = adversary.choose_spawn(b, &cells, rules);
+        b.set(spawn.cell, spawn.value);
+        spawned.push(spawn);
+    }
+    Ok(MoveResult{score, spawned})
+}
+
+// A game where an adversary places the new tiles. The starting tiles are
+// still random, from the game's player.
+pub struct AdversarialGame {
+    game: Game,
+    adversary: Box<dyn Adversary>,
+}
+
+impl AdversarialGame {
+    pub fn new(player: GamePlayer, options: GameOptions, adversary: Box<dyn Adversary>) -> Result<AdversarialGame, GameError> {
+        Ok(AdversarialGame{game: Game::new(player, options)?, adversary})
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn adversary(&self) -> &dyn Adversary {
+        self.adversary.as_ref()
+    }
+
+    pub fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        if self.game.status() != GameStatus::InProgress {
+            return Err(GameError::GameOver);
+        }
+        let mut board = self.game.board().clone();
+        let result = play_adversarial(&mut board, dir, self.adversary.as_mut(), self.game.rules())?;
+        let moves = self.game.moves() + 1;
+        self.game.restore(board, moves);
+        Ok(result)
+    }
+}
+
+impl PlayedGame for AdversarialGame {
+    fn game(&self) -> &Game {
+        &self.game
+    }
+
+    fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        AdversarialGame::play(self, dir)
+    }
+
+    fn restore(&mut self, board: Board, moves: i32) {
+        self.game.restore(board, moves)
+    }
+}
+
+#[cfg(test)]
+mod adversary_tests {
+    use super::*;
+
+    const DIRS: [MoveDir; 4] = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+
+    #[test]
+    fn test_worst_case() {
+        // A 2 in the last cell ends the game, where a 4 would merge
+        let b: Board = "2,4,2,4/4,2,4,2/2,4,2,4/4,2,4,.".parse().unwrap();
+        let mut adversary = WorstCase{score_fn: ScoreFunction::FreeSpace};
+        let spawn = adversary.choose_spawn(&b, &[15], &SpawnRules::default());
+        assert_eq!(spawn, Spawn{cell: 15, value: 2});
+        let mut after = b.clone();
+        after.set(15, 2);
+        assert!(after.stuck());
+    }
+
+    #[test]
+    fn adversarial_games() {
+        let adversary = WorstCase{score_fn: ScoreFunction::FreeSpaceWithSortedness};
+        for merge in [MergeRule::Standard, MergeRule::Threes].iter() {
+            let options = GameOptions{merge: *merge, keep_playing: true, ..GameOptions::default()};
+            let player = GamePlayer::from_seed(4).with_rules(SpawnRules::for_merge(*merge), *merge).unwrap();
+            let mut game = AdversarialGame::new(player, options, adversary.clone_box()).unwrap();
+            let (mut turn, mut moves) = (0, 0);
+            while game.game().status() == GameStatus::InProgress {
+                let before = game.game().board().clone();
+                turn += 1;
+                match game.play(DIRS[turn % 4]) {
+                    Ok(result) => {
+                        moves += 1;
+                        assert_eq!(result.spawned.len(), 1);
+                        assert_eq!(game.game().board().score, before.score + result.score);
+                    },
+                    Err(_) => assert_eq!(game.game().board(), &before),
+                }
+            }
+            assert!(game.game().board().stuck());
+            assert_eq!(game.game().moves(), moves);
+        }
+    }
+}
@@ -1,85 +1,151 @@
 use super::gameplay::*;
+use super::bitboard::BitBoard;
+use super::merge::MergeRule;
+use super::threes;
 
 extern crate flame;
 extern crate rand;
 use rand::seq::SliceRandom;
 use std::cmp::max;
+use std::time::{Duration, Instant};
 
 
 
-pub fn random(_player: &mut GamePlayer, board: &Board) -> MoveDir {
-    // Choose any of the four moves at random
-    let options: Vec<MoveDir> = vec![MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right];
-    let valid_options: Vec<MoveDir> = options.into_iter()
-        .filter(|&dir| board.is_valid_move(dir))
-        .collect();
-    
-    let mut rng = rand::thread_rng();
-    *valid_options.choose(&mut rng).unwrap()
+// A move-picking agent. Strategies may keep state between moves (caches,
+// learned weights, etc), which reset() should clear at the start of each game.
+// Strategies must be cloneable and Send so that games can be spread across
+// threads, each with its own copy.
+pub trait Strategy: StrategyClone + Send {
+    // Short name, used as the key for the strategy in reports
+    fn name(&self) -> String;
+
+    // Human readable description of the strategy's parameters
+    fn config(&self) -> String {
+        String::new()
+    }
+
+    // Choose the next move for board, or None if there is no legal move.
+    // The player may be used to try out moves, or as a source of randomness.
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir>;
+
+    // Called before the first move of every game
+    fn reset(&mut self) {}
 }
 
-pub fn random_3dir(_player: &mut GamePlayer, board: &Board) -> MoveDir {
-    // Pick one of three directions at random (arbitrarily, down is excluded)
-    // If none of those three are valid moves, then make the fourth move
-    let options: Vec<MoveDir> = vec![MoveDir::Up, MoveDir::Left, MoveDir::Right];
-    let mut valid_options: Vec<MoveDir> = options.into_iter()
-        .filter(|&dir| board.is_valid_move(dir))
-        .collect();
+pub trait StrategyClone {
+    fn clone_box(&self) -> Box<dyn Strategy>;
+}
 
-    if valid_options.len() == 0 {
-        // Move down as a last resort
-        valid_options.push(MoveDir::Down);
+impl<T: 'static + Strategy + Clone> StrategyClone for T {
+    fn clone_box(&self) -> Box<dyn Strategy> {
+        Box::new(self.clone())
     }
-    let mut rng = rand::thread_rng();
-    *valid_options.choose(&mut rng).unwrap()
 }
 
-pub fn max_free_space_3dir(player: &mut GamePlayer, board: &Board) -> MoveDir {
-    // Choose from a set of three moves, choosing the direction which results
-    // in the greatest number of empty squares on the next turn (i.e. the move 
-    // which results in the greatest number of merged tiles)
-    let options = vec![MoveDir::Up, MoveDir::Left, MoveDir::Right];
+#[derive(Clone)]
+pub struct Random;
 
-    let mut selected = None;
-    let mut best_score = -1;
+impl Strategy for Random {
+    fn name(&self) -> String {
+        "random".to_string()
+    }
 
-    for dir in options.iter() {
-        if let Ok(b) = player.play(&board, *dir) {
-            let score = score_free_space(&b);
-            if score > best_score {
-                best_score = score;
-                selected = Some(*dir);
-            }
-        }
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // Choose any of the four moves at random
+        let options: Vec<MoveDir> = vec![MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right];
+        let valid_options: Vec<MoveDir> = options.into_iter()
+            .filter(|&dir| board.is_valid_move(dir))
+            .collect();
+
+        valid_options.choose(&mut player.rng).copied()
     }
-    
-    match selected {
-        Some(dir) => dir,
-        None => MoveDir::Down
+}
+
+#[derive(Clone)]
+pub struct Random3Dir;
+
+impl Strategy for Random3Dir {
+    fn name(&self) -> String {
+        "random_3dir".to_string()
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // Pick one of three directions at random (arbitrarily, down is excluded)
+        // If none of those three are valid moves, then make the fourth move
+        let options: Vec<MoveDir> = vec![MoveDir::Up, MoveDir::Left, MoveDir::Right];
+        let valid_options: Vec<MoveDir> = options.into_iter()
+            .filter(|&dir| board.is_valid_move(dir))
+            .collect();
+
+        if valid_options.is_empty() {
+            // Move down as a last resort
+            return Some(MoveDir::Down).filter(|dir| board.is_valid_move(*dir));
+        }
+        valid_options.choose(&mut player.rng).copied()
     }
 }
 
-pub fn max_free_space(player: &mut GamePlayer, board: &Board) -> MoveDir {
-    // Choose the direction which results in the greatest number of empty 
-    // squares on the next turn (i.e. the move which results in the greatest
-    // number of merged tiles)
-    let options = vec![MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right];
+#[derive(Clone)]
+pub struct MaxFreeSpace3Dir;
 
-    let mut selected = MoveDir::Up;
-    let mut best_score = -1;
+impl Strategy for MaxFreeSpace3Dir {
+    fn name(&self) -> String {
+        "max_free_space_3dir".to_string()
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // Choose from a set of three moves, choosing the direction which results
+        // in the greatest number of empty squares on the next turn (i.e. the move 
+        // which results in the greatest number of merged tiles)
+        let options = [MoveDir::Up, MoveDir::Left, MoveDir::Right];
 
-    for dir in options.iter() {
-        if let Ok(new_board) = player.play(&board, *dir) {
-            let score = score_free_space(&new_board);
-            if score > best_score {
-                best_score = score;
-                selected = *dir;
+        let mut selected = None;
+        let mut best_score = -1;
+
+        for dir in options.iter() {
+            if let Ok(b) = player.play(board, *dir) {
+                let score = score_free_space(&b);
+                if score > best_score {
+                    best_score = score;
+                    selected = Some(*dir);
+                }
             }
         }
+
+        // Move down as a last resort
+        selected.or_else(|| Some(MoveDir::Down).filter(|dir| board.is_valid_move(*dir)))
     }
-    selected
 }
 
+#[derive(Clone)]
+pub struct MaxFreeSpace;
+
+impl Strategy for MaxFreeSpace {
+    fn name(&self) -> String {
+        "max_free_space".to_string()
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // Choose the direction which results in the greatest number of empty 
+        // squares on the next turn (i.e. the move which results in the greatest
+        // number of merged tiles)
+        let options = [MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right];
+
+        let mut selected = None;
+        let mut best_score = -1;
+
+        for dir in options.iter() {
+            if let Ok(new_board) = player.play(board, *dir) {
+                let score = score_free_space(&new_board);
+                if score > best_score {
+                    best_score = score;
+                    selected = Some(*dir);
+                }
+            }
+        }
+        selected
+    }
+}
 
 #[derive(Clone)]
 struct EvaluationNode {
@@ -105,41 +171,665 @@ fn expand_scenarios(player: &mut GamePlayer, input_set: &Vec<EvaluationNode>, sc
     out
 }
 
+#[derive(Copy, Clone, Debug)]
 pub enum ScoreFunction {
     FreeSpace,
     FreeSpaceWithSortedness
 }
 
-pub fn naive_lookahead(mut player: &mut GamePlayer, board: &Board, moves: i32, score_fn: ScoreFunction) -> MoveDir {
-    // "Naive" because it would be better, probably, to do a full minimax with all
-    // of the possible random new tiles at each turn. 
+impl ScoreFunction {
+    // Suffix distinguishing strategy names by heuristic, e.g. "lookaheadsorted3"
+    pub(crate) fn name_suffix(self) -> &'static str {
+        match self {
+            ScoreFunction::FreeSpace => "",
+            ScoreFunction::FreeSpaceWithSortedness => "sorted",
+        }
+    }
+}
 
-    let score_fn = match score_fn {
+pub(crate) fn score_function(score_fn: ScoreFunction) -> fn(&Board) -> i32 {
+    match score_fn {
         ScoreFunction::FreeSpace => score_free_space,
         ScoreFunction::FreeSpaceWithSortedness => score_free_space_sortedness,
-    };
+    }
+}
 
-    let mut nodes = vec![EvaluationNode{dir: None, board: board.clone(), rank: 0}];
-    for _ in 0..moves {
-        let new_nodes = expand_scenarios(&mut player, &nodes, score_fn);
-        if new_nodes.len() == 0 {
-            break;
-        }
-        nodes = new_nodes;
+#[derive(Clone)]
+pub struct NaiveLookahead {
+    pub moves: i32,
+    pub score_fn: ScoreFunction,
+}
+
+impl Strategy for NaiveLookahead {
+    fn name(&self) -> String {
+        format!("lookahead{}{}", self.score_fn.name_suffix(), self.moves)
+    }
+
+    fn config(&self) -> String {
+        format!("moves: {}, score_fn: {:?}", self.moves, self.score_fn)
     }
 
-    if nodes.len() > 0 {
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // "Naive" because it would be better, probably, to do a full minimax with all
+        // of the possible random new tiles at each turn. See Expectimax.
+
+        let score_fn = score_function(self.score_fn);
+
+        let mut nodes = vec![EvaluationNode{dir: None, board: board.clone(), rank: 0}];
+        for _ in 0..self.moves {
+            let new_nodes = expand_scenarios(player, &nodes, score_fn);
+            if new_nodes.is_empty() {
+                break;
+            }
+            nodes = new_nodes;
+        }
+
+        // If there were no moves at all, only the starting node is left, and
+        // it has no direction
         let best_node = nodes.iter().max_by_key(|x| x.rank);
-        best_node.unwrap().dir.unwrap()
-    } else {
-        MoveDir::Down // TODO: We should probably return a Result with error if there is no available move
+        best_node.and_then(|node| node.dir)
+    }
+}
+
+#[derive(Clone)]
+pub struct Expectimax {
+    pub depth: i32,
+    pub score_fn: ScoreFunction,
+}
+
+impl Strategy for Expectimax {
+    fn name(&self) -> String {
+        format!("expectimax{}{}", self.score_fn.name_suffix(), self.depth)
+    }
+
+    fn config(&self) -> String {
+        format!("depth: {}, score_fn: {:?}", self.depth, self.score_fn)
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // Unlike NaiveLookahead, this considers every tile that could spawn after
+        // each move. See expectimax_move.
+        expectimax_move(&player.rules, board, self.depth, &self.score_fn)
+    }
+}
+
+// Values the positions at the edge of an expectimax search
+pub trait Evaluator {
+    // The value of a position the search goes no further into. The board's
+    // score includes everything gained on the way there, so that positions
+    // reached by different merges compare fairly.
+    fn value(&self, board: &Board) -> f32;
+
+    // Whether value is meant for afterstates, the boards left by a move
+    // before its new tiles spawn, rather than for boards after the spawn
+    fn values_afterstates(&self) -> bool {
+        false
+    }
+
+    // The value of a position with no moves left
+    fn game_over_value(&self, board: &Board) -> f32 {
+        self.value(board)
+    }
+}
+
+impl Evaluator for ScoreFunction {
+    fn value(&self, board: &Board) -> f32 {
+        score_function(*self)(board) as f32
+    }
+}
+
+// The best move by an expectimax search, or None if there are no moves. Max
+// nodes take the best of our moves, and chance nodes take the average over
+// every empty cell getting each possible new tile, weighted by how likely
+// that is under the spawn rules. `depth` is the number of our moves to
+// search, after which positions are valued by the evaluator.
+// The search runs on the packed board for speed when the board and every
+// spawn value fit in one, and on the Board itself otherwise.
+pub fn expectimax_move(rules: &SpawnRules, board: &Board, depth: i32, evaluator: &dyn Evaluator) -> Option<MoveDir> {
+    let probabilities = rules.probabilities();
+    let packed_outcomes: Option<Vec<(u32, f32)>> = probabilities.iter()
+        .map(|(value, probability)| BitBoard::exponent_of(*value).map(|exponent| (exponent, *probability as f32)))
+        .collect();
+    if let (Some(bits), Some(outcomes)) = (board.to_bitboard(), packed_outcomes) {
+        let search = ExpectimaxSearch{outcomes, tiles_per_move: rules.tiles_per_move, evaluator};
+        return search.best_move(&bits, board.score, depth);
+    }
+    let outcomes = probabilities.iter().map(|(value, probability)| (*value, *probability as f32)).collect();
+    let search = ExpectimaxSearch{outcomes, tiles_per_move: rules.tiles_per_move, evaluator};
+    search.best_move(board, board.score, depth)
+}
+
+// A position the expectimax search can run on. The search keeps track of the
+// score itself, since BitBoards don't have one.
+trait SearchBoard: Sized {
+    // How a spawned tile is written: its exponent on a BitBoard, or its
+    // value on a Board
+    type Tile: Copy;
+
+    // The position after sliding, and the score gained, or None if nothing moves
+    fn slide_tiles(&self, dir: MoveDir) -> Option<(Self, i32)>;
+
+    // The cells a new tile can spawn in, after sliding from before in dir
+    fn spawn_cells(&self, before: &Self, dir: MoveDir) -> Vec<usize>;
+
+    fn with_tile(&self, cell: usize, tile: Self::Tile) -> Self;
+
+    fn to_board(&self, score: i32) -> Board;
+}
+
+impl SearchBoard for BitBoard {
+    type Tile = u32;
+
+    fn slide_tiles(&self, dir: MoveDir) -> Option<(BitBoard, i32)> {
+        let (after, add_score) = self.slide(dir);
+        Some((after, add_score)).filter(|_| after != *self)
+    }
+
+    fn spawn_cells(&self, _before: &BitBoard, _dir: MoveDir) -> Vec<usize> {
+        (0..self.count_empty() as usize).map(|nth| self.nth_empty(nth)).collect()
+    }
+
+    fn with_tile(&self, cell: usize, exponent: u32) -> BitBoard {
+        let mut spawned = *self;
+        spawned.set_exponent(cell, exponent);
+        spawned
+    }
+
+    fn to_board(&self, score: i32) -> Board {
+        Board::from_bitboard(*self, score)
+    }
+}
+
+impl SearchBoard for Board {
+    type Tile = i32;
+
+    fn slide_tiles(&self, dir: MoveDir) -> Option<(Board, i32)> {
+        self.slide(dir).ok()
+    }
+
+    fn spawn_cells(&self, before: &Board, dir: MoveDir) -> Vec<usize> {
+        let cells = if self.merge == MergeRule::Threes {
+            threes::entry_cells(before, self, dir)
+        } else {
+            (0..self.values().len()).collect()
+        };
+        cells.into_iter().filter(|cell| self.values()[*cell] == 0).collect()
+    }
+
+    fn with_tile(&self, cell: usize, value: i32) -> Board {
+        let mut spawned = self.clone();
+        spawned.set(cell, value);
+        spawned
+    }
+
+    fn to_board(&self, score: i32) -> Board {
+        let mut board = self.clone();
+        board.score = score;
+        board
+    }
+}
+
+// Everything an expectimax search needs besides the position
+struct ExpectimaxSearch<'a, B: SearchBoard> {
+    // Tiles which can spawn after a move, with their probabilities
+    outcomes: Vec<(B::Tile, f32)>,
+    tiles_per_move: usize,
+    evaluator: &'a dyn Evaluator,
+}
+
+impl<B: SearchBoard> ExpectimaxSearch<'_, B> {
+    fn best_move(&self, board: &B, score: i32, depth: i32) -> Option<MoveDir> {
+        let mut selected = None;
+        let mut best_value = None;
+        for dir in &[MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right] {
+            if let Some((after, add_score)) = board.slide_tiles(*dir) {
+                let value = self.chance_node(board, *dir, &after, score + add_score, depth - 1, self.tiles_per_move);
+                if best_value.is_none_or(|best| value > best) {
+                    best_value = Some(value);
+                    selected = Some(*dir);
+                }
+            }
+        }
+        selected
+    }
+
+    fn max_node(&self, board: &B, score: i32, depth: i32) -> f32 {
+        let mut best_value = None;
+        for dir in &[MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right] {
+            if let Some((after, add_score)) = board.slide_tiles(*dir) {
+                let value = self.chance_node(board, *dir, &after, score + add_score, depth - 1, self.tiles_per_move);
+                if best_value.is_none_or(|best| value > best) {
+                    best_value = Some(value);
+                }
+            }
+        }
+        best_value.unwrap_or_else(|| self.evaluator.game_over_value(&board.to_board(score)))
+    }
+
+    // The average over the next tile to spawn after sliding from before in
+    // dir. With several tiles to spawn per move, each is its own chance node.
+    fn chance_node(&self, before: &B, dir: MoveDir, after: &B, score: i32, depth: i32, spawns_left: usize) -> f32 {
+        if depth <= 0 && self.evaluator.values_afterstates() {
+            return self.evaluator.value(&after.to_board(score));
+        }
+        let cells = after.spawn_cells(before, dir);
+        if spawns_left == 0 || cells.is_empty() {
+            return if depth <= 0 {
+                self.evaluator.value(&after.to_board(score))
+            } else {
+                self.max_node(after, score, depth)
+            };
+        }
+        let mut total = 0.0;
+        for cell in cells.iter() {
+            for (tile, probability) in self.outcomes.iter() {
+                let spawned = after.with_tile(*cell, *tile);
+                total += self.chance_node(before, dir, &spawned, score, depth, spawns_left - 1) * probability;
+            }
+        }
+        total / cells.len() as f32
+    }
+}
+
+// How MonteCarlo plays out its rollouts
+#[derive(Copy, Clone, Debug)]
+pub enum RolloutPolicy {
+    // Any valid move, at random
+    Random,
+    // The move leaving the most free space, as MaxFreeSpace plays
+    MaxFreeSpace,
+}
+
+impl RolloutPolicy {
+    // Play on from board until no moves are left, returning the final board
+    // and the number of moves made
+    fn play_out(self, player: &mut GamePlayer, board: Board) -> (Board, i32) {
+        let mut b = board;
+        let mut moves = 0;
+        loop {
+            let next = match self {
+                RolloutPolicy::Random => Random.choose(player, &b),
+                RolloutPolicy::MaxFreeSpace => MaxFreeSpace.choose(player, &b),
+            };
+            match next.and_then(|dir| player.play(&b, dir).ok()) {
+                Some(after) => b = after,
+                None => return (b, moves),
+            }
+            moves += 1;
+        }
+    }
+}
+
+// What MonteCarlo tries to make as large as possible, averaged over rollouts
+#[derive(Copy, Clone, Debug)]
+pub enum RolloutObjective {
+    // The final score
+    Score,
+    // The number of moves before the game ends
+    Moves,
+}
+
+#[derive(Clone)]
+pub struct MonteCarlo {
+    // Rollouts to play after each move, unless the time budget runs out first
+    pub rollouts: usize,
+    pub policy: RolloutPolicy,
+    pub objective: RolloutObjective,
+    // If set, stop starting rollouts once this much time has passed, though
+    // every move always gets at least one
+    pub time_budget: Option<Duration>,
+}
+
+impl Strategy for MonteCarlo {
+    fn name(&self) -> String {
+        let policy = match self.policy {
+            RolloutPolicy::Random => "",
+            RolloutPolicy::MaxFreeSpace => "free",
+        };
+        let objective = match self.objective {
+            RolloutObjective::Score => "",
+            RolloutObjective::Moves => "moves",
+        };
+        format!("montecarlo{}{}{}", policy, objective, self.rollouts)
+    }
+
+    fn config(&self) -> String {
+        format!("rollouts: {}, policy: {:?}, objective: {:?}, time_budget: {:?}",
+            self.rollouts, self.policy, self.objective, self.time_budget)
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // Try each move, then play the game out to the end many times from
+        // there with the rollout policy, and pick the move which did best on
+        // average. The rollouts go round the moves in turn, so that if time
+        // runs out they have all had about the same number.
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        let dirs: Vec<MoveDir> = [MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right].iter()
+            .copied()
+            .filter(|dir| board.is_valid_move(*dir))
+            .collect();
+        let mut totals = vec![0.0; dirs.len()];
+        let mut played = 0;
+        while played < self.rollouts.max(1) {
+            for (dir, total) in dirs.iter().zip(totals.iter_mut()) {
+                *total += self.rollout(player, board, *dir);
+            }
+            played += 1;
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+        }
+        let mut selected = None;
+        let mut best_total = f64::MIN;
+        for (dir, total) in dirs.iter().zip(totals.iter()) {
+            if *total > best_total {
+                best_total = *total;
+                selected = Some(*dir);
+            }
+        }
+        selected
+    }
+}
+
+impl MonteCarlo {
+    // Make the move, then play on until no moves are left, returning the
+    // objective's value for the game
+    fn rollout(&self, player: &mut GamePlayer, board: &Board, dir: MoveDir) -> f64 {
+        let after = match player.play(board, dir) {
+            Ok(after) => after,
+            Err(_) => return 0.0,
+        };
+        let (end, moves) = self.policy.play_out(player, after);
+        match self.objective {
+            RolloutObjective::Score => end.score as f64,
+            RolloutObjective::Moves => (moves + 1) as f64,
+        }
+    }
+}
+
+// Settings for Mcts
+#[derive(Copy, Clone, Debug)]
+pub struct MctsConfig {
+    // Iterations of the search per move, unless the time budget runs out
+    // first. Each adds one node to the tree and plays one rollout from it.
+    pub iterations: usize,
+    // If set, stop the search once this much time has passed, though there
+    // is always at least one iteration
+    pub time_budget: Option<Duration>,
+    // UCB1's exploration constant, for values scaled to [0, 1]
+    pub exploration: f64,
+    // Progressive widening: a chance node visited n times has at most
+    // widening * n^widening_exponent children
+    pub widening: f64,
+    pub widening_exponent: f64,
+    pub policy: RolloutPolicy,
+    // Keep the part of the tree under the position actually reached for the
+    // next move, rather than starting again
+    pub reuse_tree: bool,
+}
+
+impl Default for MctsConfig {
+    fn default() -> MctsConfig {
+        MctsConfig{
+            iterations: 200,
+            time_budget: None,
+            exploration: std::f64::consts::SQRT_2,
+            widening: 1.0,
+            widening_exponent: 0.5,
+            policy: RolloutPolicy::Random,
+            reuse_tree: true,
+        }
+    }
+}
+
+// Monte Carlo tree search, using UCT. Decision nodes are positions where we
+// pick a move, choosing between their chance nodes by UCB1. Chance nodes are
+// a move from their parent, with a child for each position the spawned tiles
+// might give. Spawns are sampled by playing the move with the player, and
+// progressive widening limits how many different ones are kept, so the
+// search goes deep rather than trying every cell. A node's value is the
+// average final score of the rollouts through it.
+#[derive(Clone)]
+pub struct Mcts {
+    pub config: MctsConfig,
+    tree: Option<MctsTree>,
+}
+
+impl Mcts {
+    pub fn new(config: MctsConfig) -> Mcts {
+        Mcts{config, tree: None}
     }
 }
 
+impl Strategy for Mcts {
+    fn name(&self) -> String {
+        let policy = match self.config.policy {
+            RolloutPolicy::Random => "",
+            RolloutPolicy::MaxFreeSpace => "free",
+        };
+        format!("mcts{}{}", policy, self.config.iterations)
+    }
+
+    fn config(&self) -> String {
+        format!("{:?}", self.config)
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        let reused = if self.config.reuse_tree {
+            self.tree.take().and_then(|tree| tree.subtree(board))
+        } else {
+            None
+        };
+        let mut tree = reused.unwrap_or_else(|| MctsTree::new(board.clone()));
+        let deadline = self.config.time_budget.map(|budget| Instant::now() + budget);
+        for _ in 0..self.config.iterations.max(1) {
+            tree.iterate(player, &self.config);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+        }
+        let best = tree.best_move();
+        self.tree = Some(tree);
+        best
+    }
+
+    fn reset(&mut self) {
+        self.tree = None;
+    }
+}
+
+#[derive(Clone)]
+enum MctsNodeKind {
+    // A position to move from, with a chance node for each valid move once
+    // expanded
+    Decision { board: Board, expanded: bool, children: Vec<usize> },
+    // A move from the parent decision node, with a decision node for each
+    // position it has led to
+    Chance { dir: MoveDir, children: Vec<usize> },
+}
+
+#[derive(Clone)]
+struct MctsNode {
+    visits: u32,
+    // Sum of the values of the rollouts through this node
+    total: f64,
+    kind: MctsNodeKind,
+}
+
+// The nodes of the tree, which refer to each other by index. Node 0 is the
+// root, a decision node.
+#[derive(Clone)]
+struct MctsTree {
+    nodes: Vec<MctsNode>,
+    // The range of rollout values seen, used to scale them to [0, 1]
+    min_value: f64,
+    max_value: f64,
+}
+
+impl MctsTree {
+    fn new(board: Board) -> MctsTree {
+        MctsTree{
+            nodes: vec![MctsNode{visits: 0, total: 0.0, kind: MctsNodeKind::Decision{board, expanded: false, children: Vec::new()}}],
+            min_value: f64::MAX,
+            max_value: f64::MIN,
+        }
+    }
+
+    fn add(&mut self, kind: MctsNodeKind) -> usize {
+        self.nodes.push(MctsNode{visits: 0, total: 0.0, kind});
+        self.nodes.len() - 1
+    }
+
+    fn board(&self, node: usize) -> &Board {
+        match &self.nodes[node].kind {
+            MctsNodeKind::Decision{board, ..} => board,
+            MctsNodeKind::Chance{..} => unreachable!("chance nodes have no board"),
+        }
+    }
+
+    fn children(&self, node: usize) -> &[usize] {
+        match &self.nodes[node].kind {
+            MctsNodeKind::Decision{children, ..} | MctsNodeKind::Chance{children, ..} => children,
+        }
+    }
+
+    // One round of the search: select a path down the tree, add a node at
+    // the end, play a rollout from it and record the result along the path
+    fn iterate(&mut self, player: &mut GamePlayer, config: &MctsConfig) {
+        let mut path = vec![0];
+        let mut node = 0;
+        let value = loop {
+            // At a decision node, expanding it if this is its first visit
+            self.expand(node);
+            let chance = match self.select(node, config.exploration) {
+                Some(chance) => chance,
+                // No moves left, so the game is over here
+                None => break self.board(node).score as f64,
+            };
+            path.push(chance);
+            let (next, added) = self.sample(node, chance, player, config);
+            path.push(next);
+            if added {
+                let (end, _) = config.policy.play_out(player, self.board(next).clone());
+                break end.score as f64;
+            }
+            node = next;
+        };
+        self.min_value = self.min_value.min(value);
+        self.max_value = self.max_value.max(value);
+        for node in path {
+            self.nodes[node].visits += 1;
+            self.nodes[node].total += value;
+        }
+    }
+
+    fn expand(&mut self, node: usize) {
+        let board = match &self.nodes[node].kind {
+            MctsNodeKind::Decision{expanded: false, board, ..} => board.clone(),
+            _ => return,
+        };
+        let children: Vec<usize> = [MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right].iter()
+            .filter(|dir| board.is_valid_move(**dir))
+            .map(|dir| self.add(MctsNodeKind::Chance{dir: *dir, children: Vec::new()}))
+            .collect();
+        self.nodes[node].kind = MctsNodeKind::Decision{board, expanded: true, children};
+    }
+
+    // The chance node to try next by UCB1, trying each once first
+    fn select(&self, node: usize, exploration: f64) -> Option<usize> {
+        let parent_visits = self.nodes[node].visits.max(1) as f64;
+        let mut best = None;
+        let mut best_bound = f64::MIN;
+        for child in self.children(node) {
+            let n = &self.nodes[*child];
+            if n.visits == 0 {
+                return Some(*child);
+            }
+            let bound = self.scaled(n.total / n.visits as f64)
+                + exploration * (parent_visits.ln() / n.visits as f64).sqrt();
+            if bound > best_bound {
+                best_bound = bound;
+                best = Some(*child);
+            }
+        }
+        best
+    }
+
+    fn scaled(&self, value: f64) -> f64 {
+        if self.max_value > self.min_value {
+            (value - self.min_value) / (self.max_value - self.min_value)
+        } else {
+            0.5
+        }
+    }
+
+    // Pick the decision node to go to from a chance node, by making its move
+    // and seeing what spawns. A new position gets a new node while progressive
+    // widening allows, and after that one of the existing ones is picked at
+    // random. Returns the node, and whether it was just added.
+    fn sample(&mut self, parent: usize, chance: usize, player: &mut GamePlayer, config: &MctsConfig) -> (usize, bool) {
+        let dir = match &self.nodes[chance].kind {
+            MctsNodeKind::Chance{dir, ..} => *dir,
+            MctsNodeKind::Decision{..} => unreachable!("expected a chance node"),
+        };
+        // The move was valid when the chance node was made
+        let after = player.play(self.board(parent), dir).unwrap();
+        let children = self.children(chance);
+        if let Some(existing) = children.iter().find(|child| *self.board(**child) == after) {
+            return (*existing, false);
+        }
+        let limit = (config.widening * ((self.nodes[chance].visits + 1) as f64).powf(config.widening_exponent)).ceil().max(1.0);
+        if (children.len() as f64) < limit {
+            let added = self.add(MctsNodeKind::Decision{board: after, expanded: false, children: Vec::new()});
+            if let MctsNodeKind::Chance{children, ..} = &mut self.nodes[chance].kind {
+                children.push(added);
+            }
+            return (added, true);
+        }
+        (*children.choose(&mut player.rng).unwrap(), false)
+    }
+
+    // The most visited move from the root
+    fn best_move(&self) -> Option<MoveDir> {
+        self.children(0).iter()
+            .max_by_key(|child| self.nodes[**child].visits)
+            .map(|child| match &self.nodes[*child].kind {
+                MctsNodeKind::Chance{dir, ..} => *dir,
+                MctsNodeKind::Decision{..} => unreachable!("expected a chance node"),
+            })
+    }
+
+    // The tree under the decision node for board, two levels down (after one
+    // of our moves and its spawns), if the search reached it
+    fn subtree(&self, board: &Board) -> Option<MctsTree> {
+        let root = self.children(0).iter()
+            .flat_map(|chance| self.children(*chance).iter())
+            .find(|node| self.board(**node) == board)?;
+        // Copy the nodes under it, renumbering them in the order they're found
+        let mut tree = MctsTree{nodes: Vec::new(), min_value: self.min_value, max_value: self.max_value};
+        let mut stack: Vec<(usize, Option<usize>)> = vec![(*root, None)];
+        while let Some((old, parent)) = stack.pop() {
+            let mut node = self.nodes[old].clone();
+            match &mut node.kind {
+                MctsNodeKind::Decision{children, ..} | MctsNodeKind::Chance{children, ..} => children.clear(),
+            }
+            tree.nodes.push(node);
+            let new = tree.nodes.len() - 1;
+            if let Some(parent) = parent {
+                match &mut tree.nodes[parent].kind {
+                    MctsNodeKind::Decision{children, ..} | MctsNodeKind::Chance{children, ..} => children.push(new),
+                }
+            }
+            // Reversed so that children keep their order
+            stack.extend(self.children(old).iter().rev().map(|child| (*child, Some(new))));
+        }
+        Some(tree)
+    }
+}
 
 fn score_free_space(board: &Board) -> i32 {
     let mut count = 0;
-    for v in &board.values {
+    for v in board.values() {
         if *v == 0 {
             count += 1;
         }
@@ -157,29 +847,134 @@ fn score_free_space_sortedness(board: &Board) -> i32 {
     let mut col_score = 0;
     let mut row_scoren = 0;
     let mut col_scoren = 0;
-    for i in 0..4 {
-        for j in 0..4 {
-            if board.values[i + j * 4] == 0 {
-                empty_count += 1;
-            }
+    let (rows, cols) = (board.rows(), board.cols());
+    for v in board.values() {
+        if *v == 0 {
+            empty_count += 1;
         }
-        for j in 0..3 {
-            if board.values[i + j*4] >= board.values[i + (j+1)*4] {
-                row_score += 1;
-            } 
-            if board.values[i + j*4] <= board.values[i + (j+1)*4] {
-                row_scoren += 1;
-            }
-
-            if board.values[i*4 + j] >= board.values[i*4 + (j+1)] {
-                col_score += 1;
-            } 
-            if board.values[i*4 + j] <= board.values[i*4 + (j+1)] {
-                col_scoren += 1;
-            }
+    }
+    // Pairs with a blocker or wildcard (negative values) in them don't count
+    // either way, as those never need to be in order
+    let compare = |a: i32, b: i32, score: &mut i32, scoren: &mut i32| {
+        if a < 0 || b < 0 {
+            return;
+        }
+        if a >= b {
+            *score += 1;
+        }
+        if a <= b {
+            *scoren += 1;
+        }
+    };
+    // Each column, down the board
+    for i in 0..cols {
+        for j in 0..rows - 1 {
+            compare(board.values()[i + j*cols], board.values()[i + (j+1)*cols], &mut row_score, &mut row_scoren);
+        }
+    }
+    // Each row, across the board
+    for i in 0..rows {
+        for j in 0..cols - 1 {
+            compare(board.values()[i*cols + j], board.values()[i*cols + (j+1)], &mut col_score, &mut col_scoren);
         }
     }
 
     let sorted_score = max(row_score, row_scoren) +  max(col_score, col_scoren);
-    return empty_count*20 + sorted_score*20 + board.score;
+    empty_count*20 + sorted_score*20 + board.score
+}
+
+#[cfg(test)]
+mod algorithm_tests {
+    use super::*;
+
+    #[test]
+    fn expectimax() {
+        // The packed search is only a faster way to get the same values
+        let b: Board = "2,4,8,./4,.,16,2/8,.,2,8/2,.,.,4".parse().unwrap();
+        let evaluator = &ScoreFunction::FreeSpaceWithSortedness;
+        let packed = ExpectimaxSearch{outcomes: vec![(1, 0.9), (2, 0.1)], tiles_per_move: 1, evaluator};
+        let unpacked = ExpectimaxSearch{outcomes: vec![(2, 0.9), (4, 0.1)], tiles_per_move: 1, evaluator};
+        let bits = b.to_bitboard().unwrap();
+        assert_eq!(packed.max_node(&bits, 0, 2), unpacked.max_node(&b, 0, 2));
+        let packed_move = packed.best_move(&bits, 0, 2).map(|dir| dir as usize);
+        assert_eq!(packed_move, unpacked.best_move(&b, 0, 2).map(|dir| dir as usize));
+
+        // Boards which can't be packed are searched too, rather than handed
+        // to another strategy
+        let boards = [
+            ("65536,2,.,./.,.,.,./.,.,.,./.,.,.,.", MergeRule::Standard),
+            ("2,4,./.,.,./.,.,8", MergeRule::Standard),
+            ("1,2,./3,6,./.,.,.", MergeRule::Threes),
+            ("2,#,4/4,#,2/.,#,4", MergeRule::Standard),
+        ];
+        for (text, merge) in boards.iter() {
+            let b = Board::parse(text, *merge).unwrap();
+            assert!(b.to_bitboard().is_none());
+            let dir = expectimax_move(&SpawnRules::for_merge(*merge), &b, 2, &ScoreFunction::FreeSpace).unwrap();
+            assert!(b.is_valid_move(dir));
+        }
+        let stuck: Board = "2,8,16,32/256,16,2,16/4,8,4,8/2,4,2,4".parse().unwrap();
+        assert!(expectimax_move(&SpawnRules::default(), &stuck, 2, &ScoreFunction::FreeSpace).is_none());
+    }
+
+    #[test]
+    fn monte_carlo() {
+        // Only the two 4s can merge, so only Left and Right are moves
+        let b: Board = "2,4,8,16/4,8,16,2/8,16,2,8/16,2,4,4".parse().unwrap();
+        let mut strategy = MonteCarlo{rollouts: 5, policy: RolloutPolicy::Random, objective: RolloutObjective::Moves, time_budget: None};
+        let dir = strategy.choose(&mut GamePlayer::from_seed(1), &b);
+        assert!(dir == Some(MoveDir::Right) || dir == Some(MoveDir::Left));
+        assert_eq!(strategy.name(), "montecarlomoves5");
+
+        // The same seed makes the same choices
+        let mut player = GamePlayer::from_seed(2);
+        let start = player.new_board(StartPosition::Random);
+        let mut guided = MonteCarlo{rollouts: 3, policy: RolloutPolicy::MaxFreeSpace, objective: RolloutObjective::Score, time_budget: None};
+        let first = guided.choose(&mut GamePlayer::from_seed(3), &start);
+        assert_eq!(guided.choose(&mut GamePlayer::from_seed(3), &start), first);
+        assert!(first.is_some());
+
+        // A stuck board has nothing to choose, and a tiny time budget still
+        // gives a move
+        let stuck: Board = "2,4/4,2".parse().unwrap();
+        assert_eq!(guided.choose(&mut player, &stuck), None);
+        let mut hurried = MonteCarlo{rollouts: 1_000_000, time_budget: Some(Duration::from_millis(1)), ..guided};
+        assert!(hurried.choose(&mut player, &start).is_some());
+    }
+
+    #[test]
+    fn mcts() {
+        let b: Board = "2,4,8,16/4,8,16,2/8,16,2,8/16,2,4,4".parse().unwrap();
+        let mut strategy = Mcts::new(MctsConfig{iterations: 50, ..MctsConfig::default()});
+        let dir = strategy.choose(&mut GamePlayer::from_seed(1), &b);
+        assert!(dir == Some(MoveDir::Right) || dir == Some(MoveDir::Left));
+        let stuck: Board = "2,4/4,2".parse().unwrap();
+        assert_eq!(strategy.choose(&mut GamePlayer::from_seed(1), &stuck), None);
+
+        // Play a game, checking the tree carries over from move to move, and
+        // that widening keeps the number of spawns tried in check
+        let config = MctsConfig{iterations: 100, ..MctsConfig::default()};
+        let mut strategy = Mcts::new(config);
+        let mut game = Game::new(GamePlayer::from_seed(5), GameOptions::default()).unwrap();
+        let mut player = GamePlayer::from_seed(6);
+        let mut reused = 0;
+        for _ in 0..20 {
+            let dir = strategy.choose(&mut player, game.board()).unwrap();
+            let tree = strategy.tree.as_ref().unwrap();
+            assert_eq!(tree.board(0), game.board());
+            if tree.nodes[0].visits > config.iterations as u32 {
+                reused += 1;
+            }
+            for node in tree.nodes.iter() {
+                if let MctsNodeKind::Chance{children, ..} = &node.kind {
+                    let limit = config.widening * (node.visits as f64).powf(config.widening_exponent);
+                    assert!(children.len() as f64 <= limit.ceil().max(1.0));
+                }
+            }
+            game.play(dir).unwrap();
+        }
+        assert!(reused > 0);
+        strategy.reset();
+        assert!(strategy.tree.is_none());
+    }
 }
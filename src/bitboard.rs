@@ -0,0 +1,355 @@
+use super::gameplay::MoveDir;
+
+use std::sync::OnceLock;
+
+// Packed board representation: 16 cells of 4 bits each in a u64. Each cell
+// holds the exponent of its tile (0 = empty, 1 = 2, 2 = 4, ... 15 = 32768).
+// Cells are stored in the same row-major order as Board::values, with cell 0
+// in the least significant nibble, so each row occupies 16 contiguous bits.
+// That lets a whole row move be done with a single lookup into a table
+// indexed by the 16-bit row, and up/down moves are done by transposing the
+// board and doing left/right row moves.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BitBoard(pub u64);
+
+// Largest exponent accepted by from_values. Merging two of these yields 15,
+// which is the largest value a nibble can hold, so moves can never overflow.
+pub const MAX_EXPONENT: u32 = 14;
+
+// Bits which may be set in a packable tile value (2 through 2^MAX_EXPONENT)
+const VALUE_MASK: i32 = ((1 << (MAX_EXPONENT + 1)) - 1) & !1;
+
+const ROW_MASK: u64 = 0xFFFF;
+const CELL_MASK: u64 = 0xF;
+
+struct MoveTables {
+    // Result of moving each possible row toward cell 0 (i.e. "left")
+    left: Vec<u16>,
+    // Result of moving each possible row toward cell 3 (i.e. "right")
+    right: Vec<u16>,
+    // Score gained by a row move. A run of k equal tiles always produces k/2
+    // merges whichever way it moves, so left and right share this table.
+    score: Vec<i32>,
+}
+
+static TABLES: OnceLock<MoveTables> = OnceLock::new();
+
+fn tables() -> &'static MoveTables {
+    TABLES.get_or_init(build_tables)
+}
+
+fn unpack_row(row: u16) -> [u8; 4] {
+    let mut cells = [0u8; 4];
+    for (i, cell) in cells.iter_mut().enumerate() {
+        *cell = ((row >> (4 * i)) & 0xF) as u8;
+    }
+    cells
+}
+
+fn pack_row(cells: [u8; 4]) -> u16 {
+    let mut row = 0u16;
+    for (i, cell) in cells.iter().enumerate() {
+        row |= (*cell as u16) << (4 * i);
+    }
+    row
+}
+
+// Slide a row of exponents toward index 0, merging equal neighbors once per
+// move. Returns the new row and the score gained.
+fn slide_row_left(cells: [u8; 4]) -> ([u8; 4], i32) {
+    let mut out = [0u8; 4];
+    let mut score = 0;
+    let mut pos = 0;
+    let mut pending = 0u8;
+    for &cell in cells.iter() {
+        if cell == 0 {
+            continue;
+        }
+        if pending == 0 {
+            pending = cell;
+        } else if pending == cell && cell < 15 {
+            out[pos] = cell + 1;
+            score += 1 << (cell + 1);
+            pos += 1;
+            pending = 0;
+        } else {
+            out[pos] = pending;
+            pos += 1;
+            pending = cell;
+        }
+    }
+    if pending != 0 {
+        out[pos] = pending;
+    }
+    (out, score)
+}
+
+fn build_tables() -> MoveTables {
+    let mut left = vec![0u16; 65536];
+    let mut right = vec![0u16; 65536];
+    let mut score = vec![0i32; 65536];
+    for row in 0..65536usize {
+        let cells = unpack_row(row as u16);
+        let (moved, row_score) = slide_row_left(cells);
+        left[row] = pack_row(moved);
+        score[row] = row_score;
+
+        // Moving right is moving left on the reversed row
+        let mut reversed = cells;
+        reversed.reverse();
+        let (mut moved, _) = slide_row_left(reversed);
+        moved.reverse();
+        right[row] = pack_row(moved);
+    }
+    MoveTables{left, right, score}
+}
+
+impl BitBoard {
+    // Pack a board's cell values. Returns None if any cell is not a power of
+    // two, or exceeds 2^MAX_EXPONENT.
+    pub fn from_values(values: &[i32; 16]) -> Option<BitBoard> {
+        // This sits on the hot path of every Board move, so it is written
+        // without branches per cell and validated once at the end.
+        let mut bits = 0u64;
+        let mut invalid = 0i32;
+        for (i, v) in values.iter().enumerate() {
+            let v = *v;
+            // Anything other than a single bit in the allowed range is invalid
+            invalid |= v & v.wrapping_sub(1);
+            invalid |= v & !VALUE_MASK;
+            // An empty cell has 32 trailing zeros, which masks down to 0
+            let exp = (v as u32).trailing_zeros() & 0xF;
+            bits |= (exp as u64) << (4 * i);
+        }
+        if invalid != 0 {
+            return None;
+        }
+        Some(BitBoard(bits))
+    }
+
+    // The exponent a single cell value packs to, as in from_values
+    pub fn exponent_of(value: i32) -> Option<u32> {
+        if value & value.wrapping_sub(1) != 0 || value & !VALUE_MASK != 0 {
+            return None;
+        }
+        Some((value as u32).trailing_zeros() & 0xF)
+    }
+
+    // Whether any cell holds an exponent above MAX_EXPONENT, which only a
+    // move merging two of the largest tiles can leave behind
+    pub fn exceeds_max_exponent(self) -> bool {
+        // Only 15 is above MAX_EXPONENT, and it's the one nibble with all
+        // four bits set
+        let x = self.0;
+        x & (x >> 1) & (x >> 2) & (x >> 3) & 0x1111_1111_1111_1111 != 0
+    }
+
+    pub fn to_values(self) -> [i32; 16] {
+        let mut values = [0i32; 16];
+        for (i, v) in values.iter_mut().enumerate() {
+            // 1 << 0 would give a 1 for empty cells, so mask it off
+            let exp = self.exponent(i);
+            *v = (1 << exp) & !1;
+        }
+        values
+    }
+
+    // Exponent stored at a cell index (0 for empty)
+    pub fn exponent(self, idx: usize) -> u32 {
+        ((self.0 >> (4 * idx)) & CELL_MASK) as u32
+    }
+
+    pub fn set_exponent(&mut self, idx: usize, exp: u32) {
+        let shift = 4 * idx;
+        self.0 = (self.0 & !(CELL_MASK << shift)) | (((exp as u64) & CELL_MASK) << shift);
+    }
+
+    pub fn row(self, n: usize) -> u16 {
+        ((self.0 >> (16 * n)) & ROW_MASK) as u16
+    }
+
+    // Swap rows and columns, so that cell (r, c) moves to (c, r)
+    pub fn transpose(self) -> BitBoard {
+        let x = self.0;
+        let a1 = x & 0xF0F0_0F0F_F0F0_0F0F;
+        let a2 = x & 0x0000_F0F0_0000_F0F0;
+        let a3 = x & 0x0F0F_0000_0F0F_0000;
+        let a = a1 | (a2 << 12) | (a3 >> 12);
+        let b1 = a & 0xFF00_FF00_00FF_00FF;
+        let b2 = a & 0x00FF_00FF_0000_0000;
+        let b3 = a & 0x0000_0000_FF00_FF00;
+        BitBoard(b1 | (b2 >> 24) | (b3 << 24))
+    }
+
+    pub fn count_empty(self) -> i32 {
+        // Fold each nibble down to a single bit which is set if the nibble is
+        // non-zero, then count them
+        let mut x = self.0;
+        x |= x >> 2;
+        x |= x >> 1;
+        let occupied = (x & 0x1111_1111_1111_1111).count_ones();
+        16 - occupied as i32
+    }
+
+    // Cell index of the nth (counting from 0) empty cell
+    pub fn nth_empty(self, n: usize) -> usize {
+        let mut remaining = n;
+        for idx in 0..16 {
+            if self.exponent(idx) == 0 {
+                if remaining == 0 {
+                    return idx;
+                }
+                remaining -= 1;
+            }
+        }
+        panic!("Board has fewer than {} empty cells", n + 1);
+    }
+
+    // Slide all tiles in the given direction, without spawning a new tile.
+    // Returns the new board and the score gained by any merges.
+    pub fn slide(self, dir: MoveDir) -> (BitBoard, i32) {
+        let t = tables();
+        let (source, table) = match dir {
+            MoveDir::Left => (self, &t.left),
+            MoveDir::Right => (self, &t.right),
+            MoveDir::Up => (self.transpose(), &t.left),
+            MoveDir::Down => (self.transpose(), &t.right),
+        };
+
+        let mut out = 0u64;
+        let mut score = 0;
+        for n in 0..4 {
+            let row = source.row(n) as usize;
+            out |= (table[row] as u64) << (16 * n);
+            score += t.score[row];
+        }
+
+        let out = BitBoard(out);
+        match dir {
+            MoveDir::Up | MoveDir::Down => (out.transpose(), score),
+            _ => (out, score),
+        }
+    }
+
+    pub fn is_valid_move(self, dir: MoveDir) -> bool {
+        self.slide(dir).0 != self
+    }
+
+    pub fn stuck(self) -> bool {
+        if self.count_empty() > 0 {
+            return false;
+        }
+        !(self.is_valid_move(MoveDir::Left) || self.is_valid_move(MoveDir::Up))
+    }
+}
+
+#[cfg(test)]
+mod bitboard_tests {
+    use super::*;
+    use crate::gameplay::reduce_row;
+
+    #[test]
+    fn pack_roundtrip() {
+        let values = [0, 2, 4, 8,
+                      16, 32, 64, 128,
+                      256, 512, 1024, 2048,
+                      4096, 8192, 16384, 0];
+        let b = BitBoard::from_values(&values).unwrap();
+        assert_eq!(b.to_values(), values);
+        assert_eq!(b.count_empty(), 2);
+
+        assert!(BitBoard::from_values(&[3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+        assert!(BitBoard::from_values(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+        assert!(BitBoard::from_values(&[32768, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn transpose() {
+        let mut b = BitBoard::default();
+        for i in 0..16 {
+            b.set_exponent(i, i as u32);
+        }
+        let t = b.transpose();
+        for r in 0..4 {
+            for c in 0..4 {
+                assert_eq!(t.exponent(r * 4 + c), b.exponent(c * 4 + r));
+            }
+        }
+        assert_eq!(t.transpose(), b);
+    }
+
+    #[test]
+    fn row_tables_match_reduce_row() {
+        // Moving right must agree with reduce_row for every row made of
+        // small tiles
+        for row in 0..65536usize {
+            let cells = unpack_row(row as u16);
+            if cells.iter().any(|&c| c > 8) {
+                continue;
+            }
+            let mut values = [0i32; 4];
+            for i in 0..4 {
+                if cells[i] > 0 {
+                    values[i] = 1 << cells[i];
+                }
+            }
+            let (expected, expected_score) = reduce_row(values);
+            let t = tables();
+            let moved = unpack_row(t.right[row]);
+            for i in 0..4 {
+                let v = if moved[i] > 0 { 1 << moved[i] } else { 0 };
+                assert_eq!(v, expected[i], "row {:?}", values);
+            }
+            assert_eq!(t.score[row], expected_score, "row {:?}", values);
+        }
+    }
+
+    #[test]
+    fn slide_directions() {
+        let values = [0, 8, 0, 2,
+                      4, 8, 2, 2,
+                      4, 8, 0, 0,
+                      8, 8, 0, 0];
+        let b = BitBoard::from_values(&values).unwrap();
+
+        let (down, score) = b.slide(MoveDir::Down);
+        assert_eq!(down.to_values(), [0, 0, 0, 0,
+                                      0, 0, 0, 0,
+                                      8, 16, 0, 0,
+                                      8, 16, 2, 4]);
+        assert_eq!(score, 8 + 16 + 16 + 4);
+
+        let (up, _) = b.slide(MoveDir::Up);
+        assert_eq!(up.to_values(), [8, 16, 2, 4,
+                                    8, 16, 0, 0,
+                                    0, 0, 0, 0,
+                                    0, 0, 0, 0]);
+
+        let (left, score) = b.slide(MoveDir::Left);
+        assert_eq!(left.to_values(), [8, 2, 0, 0,
+                                      4, 8, 4, 0,
+                                      4, 8, 0, 0,
+                                      16, 0, 0, 0]);
+        assert_eq!(score, 4 + 16);
+
+        let (right, _) = b.slide(MoveDir::Right);
+        assert_eq!(right.to_values(), [0, 0, 8, 2,
+                                       0, 4, 8, 4,
+                                       0, 0, 4, 8,
+                                       0, 0, 0, 16]);
+    }
+
+    #[test]
+    fn stuck() {
+        let b = BitBoard::from_values(&[2, 8, 16, 32,
+                                        256, 16, 2, 16,
+                                        4, 8, 4, 8,
+                                        2, 4, 2, 4]).unwrap();
+        assert!(b.stuck());
+        let b = BitBoard::from_values(&[2, 8, 16, 32,
+                                        256, 16, 2, 16,
+                                        4, 8, 4, 8,
+                                        2, 4, 4, 4]).unwrap();
+        assert!(!b.stuck());
+    }
+}
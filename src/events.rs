@@ -0,0 +1,193 @@
+use super::gameplay::*;
+use super::merge::MergeRule;
+use super::threes;
+
+use serde::{Serialize, Deserialize};
+
+use rand::rngs::SmallRng;
+
+// Something that happened to a tile during a move. Applying a move's events
+// in order to the board before it gives the board after it, so renderers can
+// animate them and analysis tools can count them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MoveEvent {
+    // A tile slid to another cell without merging
+    Moved { from: usize, to: usize, value: i32 },
+    // Tiles combined into one of the given value: two of them, or three for
+    // MergeRule::PowersOfThree. from starts with the tile nearest the edge
+    // being moved towards; any of them may already be in cell to.
+    Merged { from: Vec<usize>, to: usize, value: i32 },
+    // A new tile appeared after the slide
+    Spawned(Spawn),
+}
+
+// The events for sliding the board, without making the move. Tiles which
+// stay where they are don't get an event, so a move that changes nothing has
+// none.
+pub fn slide_events(b: &Board, dir: MoveDir) -> Vec<MoveEvent> {
+    if b.merge == MergeRule::Threes {
+        return threes_slide_events(b, dir);
+    }
+    let mut events = Vec::new();
+    for n in 0..b.line_count(dir) {
+        for segment in b.line_segments(dir, n) {
+            // The cells holding this run's tiles, from the edge back
+            let cells: Vec<usize> = segment.iter().copied().filter(|cell| b.values()[*cell] != 0).collect();
+            let tiles: Vec<i32> = cells.iter().map(|cell| b.values()[*cell]).collect();
+            // The tiles land in order from the edge, each made of the next
+            // count tiles of the run
+            let mut next = 0;
+            for (landed, (value, count)) in b.merge.merge_tiles(&tiles).into_iter().enumerate() {
+                let to = segment[landed];
+                let from = &cells[next..next + count];
+                next += count;
+                if count > 1 {
+                    events.push(MoveEvent::Merged{from: from.to_vec(), to, value});
+                } else if from[0] != to {
+                    events.push(MoveEvent::Moved{from: from[0], to, value});
+                }
+            }
+        }
+    }
+    events
+}
+
+// In Threes each line (or run between blockers) moves at most one cell: the
+// first tile that can move does, and every tile behind it follows
+fn threes_slide_events(b: &Board, dir: MoveDir) -> Vec<MoveEvent> {
+    let mut events = Vec::new();
+    // The cells of each run from the edge back
+    for cells in (0..b.line_count(dir)).flat_map(|n| b.line_segments(dir, n)) {
+        let line: Vec<i32> = cells.iter().map(|cell| b.values()[*cell]).collect();
+        let first = match threes::first_move(&line) {
+            Some(first) => first,
+            None => continue,
+        };
+        let (to, from) = (cells[first - 1], cells[first]);
+        match MergeRule::Threes.merge(&line[first - 1..=first]) {
+            Some(value) if line[first - 1] != 0 => events.push(MoveEvent::Merged{from: vec![to, from], to, value}),
+            _ => events.push(MoveEvent::Moved{from, to, value: line[first]}),
+        }
+        for k in first + 1..line.len() {
+            if line[k] != 0 {
+                events.push(MoveEvent::Moved{from: cells[k], to: cells[k - 1], value: line[k]});
+            }
+        }
+    }
+    events
+}
+
+// Like play_inplace, but also returns the events of the move, ending with the
+// spawned tiles
+pub fn play_traced(b: &mut Board, dir: MoveDir, rng: &mut SmallRng, rules: &SpawnRules) -> Result<(MoveResult, Vec<MoveEvent>), GameError> {
+    let mut events = slide_events(b, dir);
+    let result = play_inplace(b, dir, rng, rules)?;
+    events.extend(result.spawned.iter().map(|spawn| MoveEvent::Spawned(*spawn)));
+    Ok((result, events))
+}
+
+impl GamePlayer {
+    pub fn play_traced(&mut self, b: &mut Board, dir: MoveDir) -> Result<(MoveResult, Vec<MoveEvent>), GameError> {
+        play_traced(b, dir, &mut self.rng, &self.rules)
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use super::*;
+
+    // Replay events onto a board, checking each one is possible
+    fn apply(b: &Board, events: &[MoveEvent]) -> Board {
+        let mut out = b.clone();
+        for event in events {
+            match event.clone() {
+                MoveEvent::Moved{from, to, value} => {
+                    assert_eq!(out.values()[from], value);
+                    assert_eq!(out.values()[to], 0);
+                    out.set(from, 0);
+                    out.set(to, value);
+                },
+                MoveEvent::Merged{from, to, value} => {
+                    let tiles: Vec<i32> = from.iter().map(|cell| out.values()[*cell]).collect();
+                    assert_eq!(b.merge.merge(&tiles), Some(value));
+                    for cell in from {
+                        out.set(cell, 0);
+                    }
+                    assert_eq!(out.values()[to], 0);
+                    out.set(to, value);
+                    out.score += value;
+                },
+                MoveEvent::Spawned(Spawn{cell, value}) => {
+                    assert_eq!(out.values()[cell], 0);
+                    out.set(cell, value);
+                },
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_slide_events() {
+        let b: Board = "2,2,2,2/.,4,.,4/8,.,.,./2,4,8,16".parse().unwrap();
+        assert_eq!(slide_events(&b, MoveDir::Right), vec![
+            MoveEvent::Merged{from: vec![3, 2], to: 3, value: 4},
+            MoveEvent::Merged{from: vec![1, 0], to: 2, value: 4},
+            MoveEvent::Merged{from: vec![7, 5], to: 7, value: 8},
+            MoveEvent::Moved{from: 8, to: 11, value: 8},
+        ]);
+        let b: Board = "2,.,2,4/.,.,.,./.,.,.,./.,.,.,.".parse().unwrap();
+        assert_eq!(slide_events(&b, MoveDir::Left), vec![
+            MoveEvent::Merged{from: vec![0, 2], to: 0, value: 4},
+            MoveEvent::Moved{from: 3, to: 1, value: 4},
+        ]);
+        let b = Board::parse("3,3,.,3,9/.,.,.,.,.", MergeRule::PowersOfThree).unwrap();
+        assert_eq!(slide_events(&b, MoveDir::Left), vec![
+            MoveEvent::Merged{from: vec![0, 1, 3], to: 0, value: 9},
+            MoveEvent::Moved{from: 4, to: 1, value: 9},
+        ]);
+        let b = Board::parse("1,2,.,3/.,.,.,1", MergeRule::Threes).unwrap();
+        assert_eq!(slide_events(&b, MoveDir::Left), vec![
+            MoveEvent::Merged{from: vec![0, 1], to: 0, value: 3},
+            MoveEvent::Moved{from: 3, to: 2, value: 3},
+            MoveEvent::Moved{from: 7, to: 6, value: 1},
+        ]);
+        let b: Board = "2,#,2,*/.,.,.,./.,.,.,./.,.,.,.".parse().unwrap();
+        assert_eq!(slide_events(&b, MoveDir::Right), vec![
+            MoveEvent::Merged{from: vec![3, 2], to: 3, value: 4},
+        ]);
+        assert_eq!(slide_events(&b, MoveDir::Up), vec![]);
+    }
+
+    #[test]
+    fn events_match_moves() {
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut player = GamePlayer::from_seed(11);
+        for game in 0..20 {
+            // Mostly standard boards, with a few of other sizes, under each
+            // merge rule
+            let (rows, cols) = [(4, 4), (4, 4), (3, 3), (5, 5), (4, 6)][game % 5];
+            let merge = MergeRule::ALL[game % MergeRule::ALL.len()];
+            player.rules = SpawnRules::for_merge(merge);
+            // and every other game with blockers and wildcards
+            let (blockers, wildcards) = if game % 2 == 0 { (0, 0) } else { (2, 1) };
+            let mut b = player.new_game_board(&GameOptions{rows, cols, merge, blockers, wildcards, ..GameOptions::default()});
+            let mut turn = game;
+            while !b.stuck() {
+                let before = b.clone();
+                turn += 1;
+                match player.play_traced(&mut b, dirs[turn % 4]) {
+                    Ok((result, events)) => {
+                        assert_eq!(apply(&before, &events), b);
+                        assert_eq!(events.last(), Some(&MoveEvent::Spawned(result.spawned[0])));
+                        let merged: i32 = events.iter().map(|e| match e {
+                            MoveEvent::Merged{value, ..} => *value,
+                            _ => 0,
+                        }).sum();
+                        assert_eq!(merged, result.score);
+                    },
+                    Err(_) => assert_eq!(b, before),
+                }
+            }
+        }
+    }
+}
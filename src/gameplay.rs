@@ -3,12 +3,24 @@
 extern crate flame;
 
 extern crate rand;
-use rand::{Rng, SeedableRng};
+use rand::{RngCore, SeedableRng};
 use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
 
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
 use std::ops::{Index, IndexMut};
+use std::str::FromStr;
 
-#[derive(Copy, Clone)]
+use super::bitboard::BitBoard;
+use super::merge::MergeRule;
+use super::threes;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MoveDir {
     Up,
     Down,
@@ -16,6 +28,57 @@ pub enum MoveDir {
     Right
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameError {
+    // The move would not change the board
+    NoOpMove(MoveDir),
+    // There are no moves left to make
+    GameOver,
+    // There is no empty cell to spawn a tile in
+    BoardFull,
+    // A tile can't be placed in the cell, because it is occupied or off the board
+    CellUnavailable(usize),
+    // A cell holds (or would hold) a value that is not a valid tile
+    InvalidBoard { cell: usize, value: i32 },
+    // Spawn rules with no value to pick, values that aren't tiles under the
+    // merge rule, or which add no tiles
+    InvalidSpawnRules,
+    // A saved board whose values don't fill its rows and columns
+    BoardSize { rows: usize, cols: usize, values: usize },
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameError::NoOpMove(dir) => write!(f, "Invalid move: {:?} does not move any tiles", dir),
+            GameError::GameOver => write!(f, "Game over: no moves left"),
+            GameError::BoardFull => write!(f, "Board is full"),
+            GameError::CellUnavailable(cell) => write!(f, "Cell {} is not available for a new tile", cell),
+            GameError::InvalidBoard { cell, value } => write!(f, "Cell {} has invalid value {}", cell, value),
+            GameError::InvalidSpawnRules => write!(f, "Spawn rules need a value with some weight, only tiles of the merge rule, and at least one tile per move"),
+            GameError::BoardSize { rows, cols, values } => write!(f, "A {}x{} board can't hold {} values", rows, cols, values),
+        }
+    }
+}
+
+impl Error for GameError {}
+
+// A tile placed on the board after a move
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spawn {
+    pub cell: usize,
+    pub value: i32,
+}
+
+// The outcome of a successful move
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveResult {
+    // Score gained by merging tiles
+    pub score: i32,
+    // New tiles, in the order they were placed
+    pub spawned: Vec<Spawn>,
+}
+
 // Dropping (probably?) in favor of the directional view
 // pub struct BoardIterator {
 //     curr: i32,
@@ -38,25 +101,29 @@ pub enum MoveDir {
 
 pub struct DirectionalView<'a> {
     dir: MoveDir,
-    values: &'a mut [i32; 16],
+    rows: usize,
+    cols: usize,
+    values: &'a mut [i32],
 }
 
 pub struct LineView<'a> {
     dir: MoveDir,
-    values: &'a mut [i32; 16],
+    rows: usize,
+    cols: usize,
+    values: &'a mut [i32],
     row: usize,
 }
 
 impl Index<usize> for LineView<'_> {
     type Output = i32;
 
-    fn index<'c>(&'c self, index: usize) -> & Self::Output {
+    fn index(&self, index: usize) -> &Self::Output {
         self.cell(self.row, index)
     }
 }
 
 impl IndexMut<usize> for LineView<'_> {
-    fn index_mut<'c>(&'c mut self, index: usize) -> & mut Self::Output {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         self.cell_mut(self.row, index)
     }
 }
@@ -64,128 +131,397 @@ impl IndexMut<usize> for LineView<'_> {
 impl LineView<'_> {
     // Return a specific element using coordinates in the "move dir" frame
     pub fn cell(&self, row: usize, col: usize) -> &i32 {
-        match self.dir {
-            MoveDir::Right => &self.values[row * 4 + col],
-            MoveDir::Left => &self.values[row * 4 + 3 - col],
-            MoveDir::Down => &self.values[row + col * 4],
-            MoveDir::Up => &self.values[row + (3 - col) * 4],
-        }
+        &self.values[line_index(self.dir, self.rows, self.cols, row, col)]
     }
     
     // Return a specific element using coordinates in the "move dir" frame
     pub fn cell_mut(&mut self, row: usize, col: usize) -> &mut i32 {
-        match self.dir {
-            MoveDir::Right => &mut self.values[row * 4 + col],
-            MoveDir::Left => &mut self.values[row * 4 + 3 - col],
-            MoveDir::Down => &mut self.values[row + col * 4],
-            MoveDir::Up => &mut self.values[row + (3 - col) * 4],
-        }
+        &mut self.values[line_index(self.dir, self.rows, self.cols, row, col)]
+    }
+
+    // Number of cells in the line
+    pub fn size(&self) -> usize {
+        line_len(self.dir, self.rows, self.cols)
     }
 }
 
 impl DirectionalView<'_> {
     // Return a specific element using coordinates in the "move dir" frame
     pub fn cell(&mut self, row: usize, col: usize) -> &mut i32 {
-        match self.dir {
-            MoveDir::Right => &mut self.values[row * 4 + col],
-            MoveDir::Left => &mut self.values[row * 4 + 3 - col],
-            MoveDir::Down => &mut self.values[row + col * 4],
-            MoveDir::Up => &mut self.values[row + (3 - col) * 4],
-        }
+        &mut self.values[line_index(self.dir, self.rows, self.cols, row, col)]
+    }
+
+    pub fn line_view(&mut self, row: usize) -> LineView<'_> {
+        LineView{row, dir: self.dir, rows: self.rows, cols: self.cols, values: self.values}
+    }
+}
+
+// Index of a cell given in the "move dir" frame: position pos along line
+// number line, where the last position is on the edge that tiles move
+// towards. Lines are rows for Left and Right, and columns for Up and Down.
+fn line_index(dir: MoveDir, rows: usize, cols: usize, line: usize, pos: usize) -> usize {
+    match dir {
+        MoveDir::Right => line * cols + pos,
+        MoveDir::Left => line * cols + cols - 1 - pos,
+        MoveDir::Down => line + pos * cols,
+        MoveDir::Up => line + (rows - 1 - pos) * cols,
     }
+}
 
-    pub fn line_view(& mut self, row: usize) -> LineView<> {
-        LineView{row, dir: self.dir, values: self.values}
+fn line_len(dir: MoveDir, rows: usize, cols: usize) -> usize {
+    match dir {
+        MoveDir::Left | MoveDir::Right => cols,
+        MoveDir::Up | MoveDir::Down => rows,
     }
 }
 
-#[derive(Clone)]
+// The standard board size
+pub const DEFAULT_SIZE: usize = 4;
+
+// Special cell values. A blocker never moves, and tiles can't pass or merge
+// with it, so it splits the lines it is in. A wildcard is a tile which merges
+// with any other tile, standing in for a copy of it (see MergeRule::merge).
+pub const BLOCKER: i32 = -1;
+pub const WILDCARD: i32 = -2;
+
+// A rows x cols grid of tiles, stored row by row in values, with 0 for an
+// empty cell. Boards default to the standard 4x4, with the standard rule for
+// merging tiles.
+//
+// The values are only reachable through values() and values_mut(), so that
+// the board can keep its packed form alongside them: standard boards are
+// moved with the BitBoard tables, and packing the cells again before every
+// move costs about as much as the move itself.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "SavedBoard")]
 pub struct Board {
-    pub values: [i32; 16],
+    rows: usize,
+    cols: usize,
+    values: Vec<i32>,
     pub score: i32,
+    pub merge: MergeRule,
+    #[serde(skip)]
+    packed: Cell<Packed>,
+}
+
+// A board as it's read back, before it's checked to be one
+#[derive(Deserialize)]
+struct SavedBoard {
+    // Older saved boards were always 4x4
+    #[serde(default = "default_size")]
+    rows: usize,
+    #[serde(default = "default_size")]
+    cols: usize,
+    values: Vec<i32>,
+    score: i32,
+    #[serde(default)]
+    merge: MergeRule,
+}
+
+impl TryFrom<SavedBoard> for Board {
+    type Error = GameError;
+
+    fn try_from(saved: SavedBoard) -> Result<Board, GameError> {
+        if saved.rows.checked_mul(saved.cols).filter(|size| *size > 0) != Some(saved.values.len()) {
+            return Err(GameError::BoardSize{rows: saved.rows, cols: saved.cols, values: saved.values.len()});
+        }
+        let mut board = Board::with_values(saved.rows, saved.cols, saved.values, saved.merge);
+        board.score = saved.score;
+        board.validate()?;
+        Ok(board)
+    }
+}
+
+// What's known about the packed form of a board's values. It's worked out
+// the first time it's needed, and kept up to date by moves and spawns, but
+// any other change to the values has to forget it.
+#[derive(Copy, Clone, Debug, Default)]
+enum Packed {
+    #[default]
+    Unknown,
+    Bits(BitBoard),
+    // Not a 4x4 board, or some cell doesn't fit in four bits
+    Unpackable,
+}
+
+impl PartialEq for Board {
+    fn eq(&self, other: &Board) -> bool {
+        self.rows == other.rows && self.cols == other.cols && self.values == other.values
+            && self.score == other.score && self.merge == other.merge
+    }
+}
+
+impl Eq for Board {}
+
+fn default_size() -> usize {
+    DEFAULT_SIZE
 }
 
 impl Default for Board {
-    fn default () -> Board {
-        // Create small, cheap to initialize and fast RNG with a random seed.
-        // The randomness is supplied by the operating system.
-        Board{values: [0; 16], score: 0}
+    fn default() -> Board {
+        Board::new(DEFAULT_SIZE, DEFAULT_SIZE)
     }
 }
 
 impl Board {
+    // An empty board of the given size
+    pub fn new(rows: usize, cols: usize) -> Board {
+        Board::with_values(rows, cols, vec![0; rows * cols], MergeRule::Standard)
+    }
 
-    pub fn row(&self, n: usize, reverse: bool) -> [i32; 4] {
-        let mut r: [i32; 4] = [0, 0, 0, 0];
-        for i in 0..4 {
-            let src = if reverse {
-                n*4 + 3 - i
-            } else {
-                n*4 + i
-            };
-            r[i as usize] = self.values[src as usize];
+    // An empty board whose tiles merge by the given rule
+    pub fn with_merge(rows: usize, cols: usize, merge: MergeRule) -> Board {
+        Board::with_values(rows, cols, vec![0; rows * cols], merge)
+    }
+
+    // A board holding the given values, row by row
+    pub fn with_values(rows: usize, cols: usize, values: Vec<i32>, merge: MergeRule) -> Board {
+        assert_eq!(values.len(), rows * cols, "a {}x{} board needs {} values", rows, cols, rows * cols);
+        Board{rows, cols, values, score: 0, merge, packed: Cell::default()}
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn values(&self) -> &[i32] {
+        &self.values
+    }
+
+    // The values for changing directly. Prefer set for a single cell, which
+    // doesn't lose track of the packed form.
+    pub fn values_mut(&mut self) -> &mut [i32] {
+        self.packed.set(Packed::Unknown);
+        &mut self.values
+    }
+
+    // Put value in a cell, whatever was there before
+    pub fn set(&mut self, cell: usize, value: i32) {
+        self.values[cell] = value;
+        let packed = match (self.packed.get(), BitBoard::exponent_of(value)) {
+            (Packed::Bits(mut bits), Some(exp)) => {
+                bits.set_exponent(cell, exp);
+                Packed::Bits(bits)
+            },
+            (Packed::Bits(_), None) => Packed::Unpackable,
+            _ => Packed::Unknown,
+        };
+        self.packed.set(packed);
+    }
+
+    // The packed form of the board, if it is a standard 4x4 board and every
+    // tile fits
+    pub fn to_bitboard(&self) -> Option<BitBoard> {
+        if self.merge != MergeRule::Standard {
+            return None;
         }
-        r
+        match self.packed.get() {
+            Packed::Bits(bits) => Some(bits),
+            Packed::Unpackable => None,
+            Packed::Unknown => {
+                let bits = if self.rows == 4 && self.cols == 4 {
+                    let mut values = [0; 16];
+                    values.copy_from_slice(&self.values);
+                    BitBoard::from_values(&values)
+                } else {
+                    None
+                };
+                self.packed.set(bits.map_or(Packed::Unpackable, Packed::Bits));
+                bits
+            },
+        }
+    }
+
+    pub fn from_bitboard(bits: BitBoard, score: i32) -> Board {
+        let mut b = Board{score, ..Board::default()};
+        b.set_bitboard(bits);
+        b
     }
 
-    pub fn col(&self, n: usize, reverse: bool) -> [i32; 4] {
-        let mut r: [i32; 4] = [0, 0, 0, 0];
+    // Replace the values of a 4x4 board with the packed ones. A move which
+    // merges two of the largest packable tiles leaves an exponent above
+    // MAX_EXPONENT, and the tables won't merge those again, so such boards
+    // are moved cell by cell like any other board holding a tile that big.
+    fn set_bitboard(&mut self, bits: BitBoard) {
+        self.values.copy_from_slice(&bits.to_values());
+        self.packed.set(if bits.exceeds_max_exponent() { Packed::Unpackable } else { Packed::Bits(bits) });
+    }
 
-        for i in 0..4 {
-            let src = if reverse {
-                n + (3 - i)*4
-            } else {
-                n + i*4
-            };
-            r[i as usize] = self.values[src as usize];
+    pub fn row(&self, n: usize, reverse: bool) -> Vec<i32> {
+        let mut r = self.values[n * self.cols..(n + 1) * self.cols].to_vec();
+        if reverse {
+            r.reverse();
+        }
+        r
+    }
+
+    pub fn col(&self, n: usize, reverse: bool) -> Vec<i32> {
+        let mut r: Vec<i32> = (0..self.rows).map(|i| self.values[n + i * self.cols]).collect();
+        if reverse {
+            r.reverse();
         }
         r
     }
 
-    pub fn set_row(&mut self, n: usize, value: [i32; 4], reverse: bool) {
-        for i in 0..4 {
+    pub fn set_row(&mut self, n: usize, value: &[i32], reverse: bool) {
+        for (i, v) in value.iter().enumerate() {
             let dst = if reverse {
-                n*4 + 3 - i
+                n * self.cols + self.cols - 1 - i
             } else {
-                n*4 + i
+                n * self.cols + i
             };
-            self.values[dst as usize] = value[i as usize];
+            self.set(dst, *v);
         }
     }
 
-    pub fn set_col(&mut self, n: usize, value: [i32; 4], reverse: bool) {
-        for i in 0..4 {
+    pub fn set_col(&mut self, n: usize, value: &[i32], reverse: bool) {
+        for (i, v) in value.iter().enumerate() {
             let dst = if reverse {
-                n + (3 - i) * 4
+                n + (self.rows - 1 - i) * self.cols
             } else {
-                n + i * 4
+                n + i * self.cols
             };
-            self.values[dst as usize] = value[i as usize];
+            self.set(dst, *v);
+        }
+    }
+
+    // Index into values of the cell at position pos along a line, in the
+    // frame of a move in direction dir (see line_count and line_len)
+    pub fn line_index(&self, dir: MoveDir, line: usize, pos: usize) -> usize {
+        line_index(dir, self.rows, self.cols, line, pos)
+    }
+
+    // Number of lines that tiles move along for a move in direction dir
+    pub fn line_count(&self, dir: MoveDir) -> usize {
+        line_len(dir, self.cols, self.rows)
+    }
+
+    // Number of cells in each line for a move in direction dir
+    pub fn line_len(&self, dir: MoveDir) -> usize {
+        line_len(dir, self.rows, self.cols)
+    }
+
+    // The cells of a line split into the runs between blockers, which tiles
+    // move along independently. Each run is in order from the edge being
+    // moved towards, which is the order its tiles land in.
+    pub fn line_segments(&self, dir: MoveDir, line: usize) -> Vec<Vec<usize>> {
+        let mut segments = vec![Vec::new()];
+        for pos in (0..self.line_len(dir)).rev() {
+            let idx = self.line_index(dir, line, pos);
+            if self.values[idx] == BLOCKER {
+                segments.push(Vec::new());
+            } else {
+                segments.last_mut().unwrap().push(idx);
+            }
         }
+        segments.retain(|segment| !segment.is_empty());
+        segments
     }
     
     pub fn blank() -> Board {
         Board::default()
     }
 
-    pub fn init() -> Board {
-        // TODO: Generate starting cell randomly
-        Board{ values: [2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], ..Board::default()}
+    // A random start: rules.start_tiles tiles in random cells, each drawn
+    // from the rules' spawn values. The standard rules give two tiles.
+    pub fn init(rows: usize, cols: usize, rng: &mut SmallRng, rules: &SpawnRules) -> Board {
+        let mut b = Board::new(rows, cols);
+        b.add_start_tiles(rng, rules);
+        b
+    }
+
+    fn add_start_tiles(&mut self, rng: &mut SmallRng, rules: &SpawnRules) {
+        for _ in 0..rules.start_tiles {
+            if spawn_random(self, rng, rules).is_err() {
+                break;
+            }
+        }
+    }
+
+    // Put the options' blockers and wildcards in random empty cells, as many
+    // as fit
+    pub(crate) fn add_special_cells(&mut self, options: &GameOptions, rng: &mut SmallRng) {
+        let specials = std::iter::repeat_n(BLOCKER, options.blockers)
+            .chain(std::iter::repeat_n(WILDCARD, options.wildcards));
+        for value in specials {
+            let empty: Vec<usize> = (0..self.values.len()).filter(|cell| self.values[*cell] == 0).collect();
+            match empty.choose(rng) {
+                Some(cell) => self.set(*cell, value),
+                None => break,
+            }
+        }
+    }
+
+    // A single 2 in the top left corner. This is how every game used to
+    // start, so it's kept for comparing against older results.
+    pub fn init_fixed(rows: usize, cols: usize) -> Board {
+        let mut b = Board::new(rows, cols);
+        b.set(0, 2);
+        b
+    }
+
+    // Slide the tiles without spawning a new one. Returns the afterstate and
+    // the score gained by the move.
+    pub fn slide(&self, dir: MoveDir) -> Result<(Board, i32), GameError> {
+        let mut after = self.clone();
+        let add_score = slide_inplace(&mut after, dir)?;
+        Ok((after, add_score))
+    }
+
+    // Place a tile of the given value in an empty cell
+    pub fn spawn(&mut self, idx: usize, value: i32) -> Result<(), GameError> {
+        if !self.merge.is_tile(value) {
+            return Err(GameError::InvalidBoard{cell: idx, value});
+        }
+        match self.values.get(idx) {
+            Some(0) => {
+                self.set(idx, value);
+                Ok(())
+            },
+            _ => Err(GameError::CellUnavailable(idx)),
+        }
+    }
+
+    // Value of the largest tile on the board
+    pub fn largest(&self) -> i32 {
+        *self.values.iter().max().unwrap()
+    }
+
+    // Check that every cell is empty, a blocker, a wildcard or a valid tile
+    pub fn validate(&self) -> Result<(), GameError> {
+        for (cell, value) in self.values.iter().enumerate() {
+            if !matches!(*value, 0 | BLOCKER | WILDCARD) && !self.merge.is_tile(*value) {
+                return Err(GameError::InvalidBoard{cell, value: *value});
+            }
+        }
+        Ok(())
     }
 
     // Faster way to test if a move is valid than to fully execute the move
     pub fn is_valid_move(&self, dir: MoveDir) -> bool {
-        // We just need to find one non-zero cell that will merge into a cell,
-        // i.e. has a next cell that is 0 or of the same value
-        for i in 0..4usize {
-            let row = match dir {
-                MoveDir::Up => self.col(i, true),
-                MoveDir::Down => self.col(i, false),
-                MoveDir::Left => self.row(i, true),
-                MoveDir::Right => self.row(i, false),
-            };
-            for j in 0..3 {
-                if row[j as usize] != 0 && row[(j + 1) as usize] == 0 || row[j as usize] == row[(j+1) as usize] {
+        if let Some(bits) = self.to_bitboard() {
+            return bits.is_valid_move(dir);
+        }
+        // We just need to find one run of cells between blockers where a
+        // tile has an empty cell somewhere ahead of it, or where some of the
+        // tiles merge
+        let mut tiles = Vec::with_capacity(self.line_len(dir));
+        for line in 0..self.line_count(dir) {
+            for cells in self.line_segments(dir, line) {
+                tiles.clear();
+                let mut gap = false;
+                for cell in cells {
+                    match self.values[cell] {
+                        0 => gap = true,
+                        _ if gap => return true,
+                        value => tiles.push(value),
+                    }
+                }
+                if self.merge.can_merge(&tiles) {
                     return true;
                 }
             }
@@ -194,13 +530,15 @@ impl Board {
     }
 
     pub fn stuck(&self) -> bool {
+        if let Some(bits) = self.to_bitboard() {
+            return bits.stuck();
+        }
         // Might think about how to do this faster or whatever, but for now 
         // we just try all four moves, but also use the simple rule that if 
         // there are any zeros, we cannot be stuck as I think it will save time. 
-        for x in self.values.iter() {
-            if *x == 0 {
-                return false;
-            }
+        // That doesn't hold with blockers, which can wall off an empty cell.
+        if !self.values.contains(&BLOCKER) && self.values.contains(&0) {
+            return false;
         }
         for dir in &[MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right] {
             if self.is_valid_move(*dir) {
@@ -218,10 +556,8 @@ impl Board {
     // For example, for directional_iter(0, MoveDir::Up), you will be 
     // returned column 0 of the board, reversed so that row 3 is in the 
     // first location of the returned array. 
-    // This returns a copy of the row as an array instead of an iterator
-    // because I'm assuming copying the 4 words will be faster, but I haven't
-    // tried it.
-    pub fn directional_row(&self, n: usize, dir: MoveDir) -> [i32; 4] {
+    // This returns a copy of the row instead of an iterator.
+    pub fn directional_row(&self, n: usize, dir: MoveDir) -> Vec<i32> {
         let (reverse, row) = match dir {
             MoveDir::Up => (true, false),
             MoveDir::Down => (false, false),
@@ -230,9 +566,9 @@ impl Board {
         };
 
         if row {
-            return self.row(n, reverse);
+            self.row(n, reverse)
         } else {
-            return self.col(n, reverse);
+            self.col(n, reverse)
         }
     }
 
@@ -258,40 +594,589 @@ impl Board {
     //     }
     // }
 
-    pub fn directional_view(&mut self, dir: MoveDir) -> DirectionalView {
-        DirectionalView{dir, values: &mut self.values}
+    pub fn directional_view(&mut self, dir: MoveDir) -> DirectionalView<'_> {
+        let (rows, cols) = (self.rows, self.cols);
+        DirectionalView{dir, rows, cols, values: self.values_mut()}
+    }
+}
+
+// Boards as text: a line per row of space separated values, with "." for
+// an empty cell, "#" for a blocker and "*" for a wildcard, e.g.
+//
+//  128   2   2   8
+//  256   8  16   8
+//  256   8   .   .
+//   64  32   .   .
+//
+// The compact form puts the same board on one line, with "," between cells
+// and "/" between rows: "128,2,2,8/256,8,16,8/256,8,.,./64,32,.,.". Parsing
+// accepts either form (and "0" for an empty cell), and takes the board's size
+// from the number of rows and cells per row. The score isn't part of the
+// text, so parsed boards start from a score of 0.
+impl Board {
+    pub fn to_compact(&self) -> String {
+        let rows: Vec<String> = (0..self.rows).map(|r| {
+            let cells: Vec<String> = self.row(r, false).iter().map(|v| cell_text(*v)).collect();
+            cells.join(",")
+        }).collect();
+        rows.join("/")
+    }
+}
+
+fn cell_text(value: i32) -> String {
+    match value {
+        0 => ".".to_string(),
+        BLOCKER => "#".to_string(),
+        WILDCARD => "*".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Every column gets the width of the widest value, so the grid lines up
+        let width = self.values.iter().map(|v| cell_text(*v).len()).max().unwrap_or(1);
+        for r in 0..self.rows {
+            if r > 0 {
+                writeln!(f)?;
+            }
+            let cells: Vec<String> = self.row(r, false).iter()
+                .map(|v| format!("{:>width$}", cell_text(*v), width = width))
+                .collect();
+            write!(f, "{}", cells.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = f.debug_struct("Board");
+        out.field("values", &format_args!("{}", self.to_compact()))
+            .field("score", &self.score);
+        if self.merge != MergeRule::Standard {
+            out.field("merge", &self.merge);
+        }
+        out.finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseBoardError {
+    // There are no rows
+    Empty,
+    // A row doesn't have as many cells as the first
+    RowLength { row: usize, len: usize, expected: usize },
+    // A cell is neither a number nor "."
+    BadCell { row: usize, col: usize, text: String },
+    // A cell is a number, but not a tile under the board's merge rule
+    InvalidTile { row: usize, col: usize, value: i32, merge: MergeRule },
+}
+
+impl fmt::Display for ParseBoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseBoardError::Empty => write!(f, "Board has no rows"),
+            ParseBoardError::RowLength { row, len, expected } => write!(f,
+                "Row {} has {} cells, expected {}", row, len, expected),
+            ParseBoardError::BadCell { row, col, text } => write!(f,
+                "Cell {} of row {} is \"{}\", expected a number or \".\"", col, row, text),
+            ParseBoardError::InvalidTile { row, col, value, merge } => write!(f,
+                "Cell {} of row {} is {}, which is not a valid tile for the {} merge rule", col, row, value, merge.name()),
+        }
+    }
+}
+
+impl Error for ParseBoardError {}
+
+impl FromStr for Board {
+    type Err = ParseBoardError;
+
+    fn from_str(s: &str) -> Result<Board, ParseBoardError> {
+        Board::parse(s, MergeRule::Standard)
+    }
+}
+
+impl Board {
+    // Parse a board whose tiles merge by the given rule, which decides what
+    // counts as a valid tile
+    pub fn parse(s: &str, merge: MergeRule) -> Result<Board, ParseBoardError> {
+        let rows: Vec<Vec<&str>> = if s.contains('/') {
+            s.trim().split('/').map(|row| row.split(',').map(|cell| cell.trim()).collect()).collect()
+        } else {
+            s.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.split_whitespace().collect())
+                .collect()
+        };
+        if rows.is_empty() {
+            return Err(ParseBoardError::Empty);
+        }
+
+        let cols = rows[0].len();
+        let mut board = Board::with_merge(rows.len(), cols, merge);
+        for (row, cells) in rows.iter().enumerate() {
+            if cells.len() != cols {
+                return Err(ParseBoardError::RowLength{row, len: cells.len(), expected: cols});
+            }
+            for (col, text) in cells.iter().enumerate() {
+                let value = match *text {
+                    "." => 0,
+                    "#" => BLOCKER,
+                    "*" => WILDCARD,
+                    _ => {
+                        let value = text.parse().map_err(|_| ParseBoardError::BadCell{row, col, text: text.to_string()})?;
+                        if value != 0 && !merge.is_tile(value) {
+                            return Err(ParseBoardError::InvalidTile{row, col, value, merge});
+                        }
+                        value
+                    },
+                };
+                board.set(row * cols + col, value);
+            }
+        }
+        Ok(board)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StartPosition {
+    // Two random tiles, see Board::init
+    #[default]
+    Random,
+    // See Board::init_fixed
+    Fixed,
+}
+
+// How new tiles appear. The standard game adds two tiles at the start and
+// one after every move, each a 2 nine times in ten and otherwise a 4.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpawnRules {
+    // The values a new tile can have, as (value, weight). Each value is
+    // picked with probability weight / (sum of the weights).
+    pub values: Vec<(i32, u32)>,
+    // Tiles added after each move. If there isn't room for them all, as many
+    // as fit are added.
+    pub tiles_per_move: usize,
+    // Tiles on the board at the start of a game with StartPosition::Random
+    pub start_tiles: usize,
+}
+
+impl Default for SpawnRules {
+    fn default() -> SpawnRules {
+        SpawnRules{values: vec![(2, 9), (4, 1)], tiles_per_move: 1, start_tiles: 2}
+    }
+}
+
+impl SpawnRules {
+    // The usual spawns for a game with the given merge rule: the two
+    // smallest tiles for Fibonacci and powers of three, and for SumEqual a
+    // mix of values that aren't all powers of two
+    pub fn for_merge(merge: MergeRule) -> SpawnRules {
+        let values = match merge {
+            MergeRule::Standard => vec![(2, 9), (4, 1)],
+            MergeRule::Fibonacci => vec![(1, 9), (2, 1)],
+            MergeRule::PowersOfThree => vec![(3, 9), (9, 1)],
+            MergeRule::SumEqual => vec![(2, 6), (3, 3), (5, 1)],
+            // As often as they come up in the Threes deck
+            MergeRule::Threes => return SpawnRules{values: vec![(1, 1), (2, 1), (3, 1)], tiles_per_move: 1, start_tiles: 9},
+        };
+        SpawnRules{values, ..SpawnRules::default()}
+    }
+
+    // Check the rules can spawn tiles in a game with the given merge rule:
+    // some value must have weight, the values must all be tiles under the
+    // merge rule, and every move must add at least one tile
+    pub fn validate(&self, merge: MergeRule) -> Result<(), GameError> {
+        let all_tiles = self.values.iter().all(|(value, _)| merge.is_tile(*value));
+        if self.total_weight() == 0 || self.tiles_per_move == 0 || !all_tiles {
+            return Err(GameError::InvalidSpawnRules);
+        }
+        Ok(())
+    }
+
+    pub fn is_valid(&self, merge: MergeRule) -> bool {
+        self.validate(merge).is_ok()
+    }
+
+    // The chance of each value for a new tile
+    pub fn probabilities(&self) -> Vec<(i32, f64)> {
+        let total = self.total_weight() as f64;
+        self.values.iter()
+            .filter(|(_, weight)| *weight > 0)
+            .map(|(value, weight)| (*value, *weight as f64 / total))
+            .collect()
+    }
+
+    fn total_weight(&self) -> u64 {
+        self.values.iter().map(|(_, weight)| *weight as u64).sum()
+    }
+
+    // The value whose share of the total weight contains r. The rules must
+    // be valid, so that there is some weight to share out.
+    pub(crate) fn pick_value(&self, r: u64) -> i32 {
+        let mut r = r % self.total_weight();
+        for (value, weight) in &self.values {
+            if r < *weight as u64 {
+                return *value;
+            }
+            r -= *weight as u64;
+        }
+        unreachable!("r is less than the total weight")
     }
 }
 
 // Encapsulate an RNG, because I dont want to initialize a new one every time we play
 pub struct GamePlayer {
     pub rng: SmallRng,
+    // The tiles this player spawns
+    pub rules: SpawnRules,
 }
 
 impl Default for GamePlayer {
     fn default () -> GamePlayer {
         // Create small, cheap to initialize and fast RNG with a random seed.
         // The randomness is supplied by the operating system.
-        GamePlayer{rng: SmallRng::from_entropy()}
+        GamePlayer{rng: SmallRng::from_entropy(), rules: SpawnRules::default()}
     }
 }
 
 impl GamePlayer {
-    pub fn play(&mut self, b: &Board, dir: MoveDir) -> Result<Board, String> {
-        let mut new_board = Board{..*b};
-        return match self.play_inplace(&mut new_board, dir) {
-            Ok(_) => Ok(new_board),
-            Err(message) => Err(message),
-        } 
+    // A player whose tile spawns (and anything else drawn from its RNG) are
+    // the same every time for a given seed
+    pub fn from_seed(seed: u64) -> GamePlayer {
+        GamePlayer{rng: SmallRng::seed_from_u64(seed), rules: SpawnRules::default()}
+    }
+
+    // The same player, spawning tiles by other rules, which must be valid
+    // for games with the given merge rule
+    pub fn with_rules(self, rules: SpawnRules, merge: MergeRule) -> Result<GamePlayer, GameError> {
+        rules.validate(merge)?;
+        Ok(GamePlayer{rules, ..self})
+    }
+
+    // Set up a standard size board for a new game, drawing any random tiles
+    // from this player's RNG
+    pub fn new_board(&mut self, start: StartPosition) -> Board {
+        self.new_board_of_size(start, DEFAULT_SIZE, DEFAULT_SIZE)
+    }
+
+    pub fn new_board_of_size(&mut self, start: StartPosition, rows: usize, cols: usize) -> Board {
+        match start {
+            StartPosition::Random => Board::init(rows, cols, &mut self.rng, &self.rules),
+            StartPosition::Fixed => Board::init_fixed(rows, cols),
+        }
+    }
+
+    // Set up the board for a new game with the given options, including its
+    // size, merge rule, blockers and wildcards
+    pub fn new_game_board(&mut self, options: &GameOptions) -> Board {
+        let mut b = Board::with_merge(options.rows, options.cols, options.merge);
+        match options.start {
+            StartPosition::Random => {
+                b.add_special_cells(options, &mut self.rng);
+                b.add_start_tiles(&mut self.rng, &self.rules);
+            },
+            StartPosition::Fixed => {
+                b.set(0, options.merge.start_tile());
+                b.add_special_cells(options, &mut self.rng);
+            },
+        }
+        b
+    }
+
+    pub fn play(&mut self, b: &Board, dir: MoveDir) -> Result<Board, GameError> {
+        // Strategies call this a lot to try out moves, so skip building a
+        // MoveResult they would throw away
+        let mut new_board = b.clone();
+        if b.merge == MergeRule::Threes {
+            threes::play_inplace(&mut new_board, dir, &mut self.rng, &self.rules)?;
+            return Ok(new_board);
+        }
+        slide_inplace(&mut new_board, dir)?;
+        for _ in 0..self.rules.tiles_per_move {
+            if spawn_random(&mut new_board, &mut self.rng, &self.rules).is_err() {
+                break;
+            }
+        }
+        Ok(new_board)
+    }
+
+    pub fn play_inplace(&mut self, b: &mut Board, dir: MoveDir) -> Result<MoveResult, GameError> {
+        play_inplace(b, dir, &mut self.rng, &self.rules)
     }
 
-    pub fn play_inplace(&mut self, b: &mut Board, dir: MoveDir) -> Result<bool, String> {
-        play_inplace(b, dir, &mut self.rng)
+    // Place a random new tile on the board
+    pub fn spawn(&mut self, b: &mut Board) -> Result<Spawn, GameError> {
+        spawn_random(b, &mut self.rng, &self.rules)
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameStatus {
+    InProgress,
+    // The target tile was reached
+    Won,
+    // No moves are left
+    Lost,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameOptions {
+    pub start: StartPosition,
+    // Reaching a tile of this value wins the game. It should be a tile under
+    // the merge rule (see GameOptions::for_merge).
+    pub target: i32,
+    // If set, the game carries on after the target is reached until no moves
+    // are left, and it still counts as won. Otherwise winning ends the game.
+    pub keep_playing: bool,
+    // Size of the board for new games. Options saved before other sizes were
+    // supported are for the standard board.
+    #[serde(default = "default_size")]
+    pub rows: usize,
+    #[serde(default = "default_size")]
+    pub cols: usize,
+    // How tiles merge. The spawn rules should only spawn tiles that are
+    // valid under it (see SpawnRules::for_merge).
+    #[serde(default)]
+    pub merge: MergeRule,
+    // Blockers and wildcards put in random cells at the start of the game
+    #[serde(default)]
+    pub blockers: usize,
+    #[serde(default)]
+    pub wildcards: usize,
+}
+
+impl Default for GameOptions {
+    fn default() -> GameOptions {
+        GameOptions{
+            start: StartPosition::Random,
+            target: 2048,
+            keep_playing: false,
+            rows: DEFAULT_SIZE,
+            cols: DEFAULT_SIZE,
+            merge: MergeRule::Standard,
+            blockers: 0,
+            wildcards: 0,
+        }
+    }
+}
+
+impl GameOptions {
+    // The default options, but for games where tiles merge by another rule,
+    // with the target that rule's counterpart of 2048
+    pub fn for_merge(merge: MergeRule) -> GameOptions {
+        GameOptions{merge, target: merge.default_target(), ..GameOptions::default()}
+    }
+}
+
+// A game in progress: the board, the player supplying its random tiles, and
+// what has happened so far
+pub struct Game {
+    board: Board,
+    player: GamePlayer,
+    options: GameOptions,
+    moves: i32,
+    won: bool,
+    status: GameStatus,
+}
+
+impl Game {
+    // A new game, which fails if the player's spawn rules aren't valid for
+    // the game's merge rule
+    pub fn new(mut player: GamePlayer, options: GameOptions) -> Result<Game, GameError> {
+        player.rules.validate(options.merge)?;
+        let board = player.new_game_board(&options);
+        Ok(Game::with_board(board, player, options))
+    }
+
+    // Pick up a game from an arbitrary position
+    pub fn with_board(board: Board, player: GamePlayer, options: GameOptions) -> Game {
+        let mut game = Game{board, player, options, moves: 0, won: false, status: GameStatus::InProgress};
+        game.update_status();
+        game
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn options(&self) -> &GameOptions {
+        &self.options
+    }
+
+    // The rules the game's tiles are spawned by
+    pub fn rules(&self) -> &SpawnRules {
+        &self.player.rules
+    }
+
+    pub fn status(&self) -> GameStatus {
+        self.status
+    }
+
+    // Number of moves made so far
+    pub fn moves(&self) -> i32 {
+        self.moves
+    }
+
+    // True once the target tile has been reached, even if play carried on
+    pub fn has_won(&self) -> bool {
+        self.won
+    }
+
+    pub fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        if self.status != GameStatus::InProgress {
+            return Err(GameError::GameOver);
+        }
+        let result = self.player.play_inplace(&mut self.board, dir)?;
+        self.moves += 1;
+        self.update_status();
+        Ok(result)
+    }
+
+    // Jump to another position in this game, such as one from its history,
+    // recomputing the status from there. Tiles never shrink, so whether the
+    // target was reached can be read from the board alone.
+    pub fn restore(&mut self, board: Board, moves: i32) {
+        self.board = board;
+        self.moves = moves;
+        self.won = false;
+        self.update_status();
+    }
+
+    fn update_status(&mut self) {
+        if self.board.largest() >= self.options.target {
+            self.won = true;
+        }
+        self.status = if self.won && !self.options.keep_playing {
+            GameStatus::Won
+        } else if !self.board.stuck() {
+            GameStatus::InProgress
+        } else if self.won {
+            GameStatus::Won
+        } else {
+            GameStatus::Lost
+        };
+    }
+}
+
+// A game being played, whichever way its new tiles come: Game spawns them
+// at random, AdversarialGame has them placed and ThreesGame deals them. Each
+// keeps its board, moves and status in a Game.
+pub trait PlayedGame {
+    fn game(&self) -> &Game;
+
+    fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError>;
+
+    // See Game::restore
+    fn restore(&mut self, board: Board, moves: i32);
+}
+
+impl PlayedGame for Game {
+    fn game(&self) -> &Game {
+        self
+    }
+
+    fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        Game::play(self, dir)
+    }
+
+    fn restore(&mut self, board: Board, moves: i32) {
+        Game::restore(self, board, moves)
+    }
+}
+
+// A full move: slide the tiles, then spawn new ones at random
+pub fn play_inplace(b: &mut Board, dir: MoveDir, rng: &mut SmallRng, rules: &SpawnRules) -> Result<MoveResult, GameError> {
+    // New tiles come in from the edge in Threes
+    if b.merge == MergeRule::Threes {
+        return threes::play_inplace(b, dir, rng, rules);
+    }
+    let score = slide_inplace(b, dir)?;
+    let mut spawned = Vec::with_capacity(rules.tiles_per_move);
+    // A move always leaves at least one empty cell, so the first tile fits
+    spawned.push(spawn_random(b, rng, rules)?);
+    for _ in 1..rules.tiles_per_move {
+        match spawn_random(b, rng, rules) {
+            Ok(spawn) => spawned.push(spawn),
+            Err(_) => break,
+        }
+    }
+    Ok(MoveResult{score, spawned})
+}
+
+// The error for a move which doesn't change the board
+fn no_op_error(b: &Board, dir: MoveDir) -> GameError {
+    if b.stuck() {
+        GameError::GameOver
+    } else {
+        GameError::NoOpMove(dir)
+    }
+}
+
+// Slide all tiles in the given direction without spawning a new tile, leaving
+// the deterministic "afterstate" of the move. Returns the score gained by
+// merges, which is also added to the board's score. The board is untouched if
+// nothing can move.
+pub fn slide_inplace(b: &mut Board, dir: MoveDir) -> Result<i32, GameError> {
+    // Use the packed representation and its lookup tables whenever the board
+    // fits in one, and fall back to moving cell by cell otherwise.
+    if let Some(bits) = b.to_bitboard() {
+        let (moved, add_score) = bits.slide(dir);
+        if moved == bits {
+            return Err(no_op_error(b, dir))
+        }
+        b.set_bitboard(moved);
+        b.score += add_score;
+        return Ok(add_score);
+    }
+
+    // Anything the packed board can't hold may not be a valid tile at all
+    b.validate()?;
+    let start_score = b.score;
+    let moved = if b.merge == MergeRule::Threes {
+        threes::slide_values(b, dir)
+    } else {
+        slide_values(b, dir)
+    };
+    if !moved {
+        return Err(no_op_error(b, dir))
+    }
+    Ok(b.score - start_score)
+}
 
-pub fn play_inplace(b: &mut Board, dir: MoveDir, rng: &mut SmallRng) -> Result<bool, String> {
+// Place a new tile in a random empty cell, with its value drawn from the
+// rules. Returns the cell and value chosen.
+pub fn spawn_random(b: &mut Board, rng: &mut SmallRng, rules: &SpawnRules) -> Result<Spawn, GameError> {
+    let zero_count = b.values.iter().filter(|v| **v == 0).count();
+    if zero_count == 0 {
+        return Err(GameError::BoardFull)
+    }
+    let (nth, value) = random_spawn(zero_count as i32, rng, rules);
+    let idx = b.values.iter()
+        .enumerate()
+        .filter(|(_, v)| **v == 0)
+        .nth(nth)
+        .unwrap()
+        .0;
+    b.spawn(idx, value)?;
+    Ok(Spawn{cell: idx, value})
+}
+
+// Pick which of the empty cells gets the new tile, and its value
+fn random_spawn(empty_count: i32, rng: &mut SmallRng, rules: &SpawnRules) -> (usize, i32) {
+    // Both choices come from a single draw, since the RNG is a good fraction
+    // of the cost of a move. The low half picks the cell by scaling it into
+    // [0, empty_count), which is biased by at most 16 parts in 2^32, and the
+    // high half picks the value.
+    let r = rng.next_u64();
+    let nth = (((r & 0xFFFF_FFFF) * empty_count as u64) >> 32) as usize;
+    (nth, rules.pick_value(r >> 32))
+}
+
+// Slide all tiles in the given direction by walking the cells of each row.
+// Returns true if any tile moved.
+fn slide_values(b: &mut Board, dir: MoveDir) -> bool {
     // //let _guard = flame::start_guard("play");
     // let mut new = Board{ values: b.values, score: b.score };
 
@@ -323,98 +1208,40 @@ pub fn play_inplace(b: &mut Board, dir: MoveDir, rng: &mut SmallRng) -> Result<b
     //     new.score += add_score;
     // }
 
-    //// Method #3
+    //// Method #4
+    // Method #3 walked each line merging equal neighbours in place, which
+    // only works for pairs of equal tiles. Now each line's tiles are gathered
+    // up, merged by the board's rule, and written back.
     let mut changed = false;
-    for row in 0..4 {
-        let (start, step) = match dir {
-            MoveDir::Right => (row * 4i32, 1i32),
-            MoveDir::Left => (row * 4i32 + 3, -1i32),
-            MoveDir::Down => (row, 4),
-            MoveDir::Up => (12 + row, -4),
-        };
-
-        for slot in (1..4_i32).rev() {
-            let cur_pos = slot;
-            let mut next_pos = slot-1;
-            while next_pos >= 0 {
-                let cur_idx = (start + cur_pos * step) as usize;
-                let next_idx = (start + next_pos * step) as usize;
-                //let mut next = &mut new.values[(start + next_idx * step) as usize];
-                if b.values[next_idx] == 0 {
-                    next_pos -= 1;
-                    continue;
-                } else if b.values[next_idx] == b.values[cur_idx] {
-                    b.score += b.values[cur_idx] * 2;
-                    b.values[cur_idx] *= 2;
-                    b.values[next_idx] = 0;
-                    changed = true;
-                    break;
-                } else if b.values[cur_idx] == 0 {
-                    b.values[cur_idx] = b.values[next_idx];
-                    b.values[next_idx] = 0;
+    let mut tiles = Vec::with_capacity(b.line_len(dir));
+    for line in 0..b.line_count(dir) {
+        // Tiles can't pass blockers, so each run between them moves on its own
+        for cells in b.line_segments(dir, line) {
+            tiles.clear();
+            tiles.extend(cells.iter().map(|cell| b.values[*cell]).filter(|value| *value != 0));
+            let merged = b.merge.merge_tiles(&tiles);
+            for (n, cell) in cells.iter().enumerate() {
+                let value = merged.get(n).map_or(0, |(value, _)| *value);
+                if b.values[*cell] != value {
+                    b.values_mut()[*cell] = value;
                     changed = true;
-                    next_pos = cur_pos - 1; // Restart search with new current position value
-                    continue;
-                } else {
-                    break; // The next row is not combinable
                 }
             }
+            b.score += merged.iter().filter(|(_, count)| *count > 1).map(|(value, _)| value).sum::<i32>();
         }
-        
-    }
-    
-    if !changed {
-        return Err(String::from("Invalid move"))
-    }
-
-    // // Collect list of 0's
-    // let mut zeros: Vec<i32> = Vec::new();
-    // for i in 0..16 {
-    //     if new.values[i] == 0 {
-    //         zeros.push(i as i32);
-    //     }
-    // }
-    // // Pick a random 0 cell
-    // let set_idx = zeros.choose(&mut rng).unwrap();
-
-    let mut zero_count = 0;
-    for i in 0..16 {
-        if b.values[i] == 0 {
-            zero_count += 1;
-        }
-    }
-    zero_count = rng.gen_range(0, zero_count) + 1; 
-    let mut set_idx = 0;
-    for i in 0..16 {
-        if b.values[i] == 0 {
-            zero_count -= 1;
-        }
-
-        if zero_count == 0 {
-            set_idx = i;
-            break;
-        }
-    }
-
-    // 1 in 10 chance of being a 4, otherwise its a 2
-    let rval = rng.gen_range(0, 10);
-    if rval < 9 {
-        b.values[set_idx as usize] = 2;
-    } else {
-        b.values[set_idx as usize] = 4;
     }
-    
-    Ok(true)
+    changed
 }
 
-// Defines row/column reduction rules. It assumes movement is "right", i.e. from 
-// index 0 towards index 3.
+// Defines row/column reduction rules for the standard merge rule. It assumes
+// movement is "right", i.e. from index 0 towards the last index.
 pub fn reduce_row_inplace(row: &mut LineView) -> i32 {
+    let last = row.size() - 1;
     // First, shift right as needed until there are no empty (value = 0) 
     // cells to the right of non-empty ones
     let mut i = 1;
     let mut add_score = 0;
-    while i <= 3 {
+    while i <= last {
         if row[i] != 0 {
             i += 1; 
             continue;
@@ -427,7 +1254,7 @@ pub fn reduce_row_inplace(row: &mut LineView) -> i32 {
     }
 
     // Now combine matching neighbors, starting from the right
-    i = 3;
+    i = last;
     while i > 0 {
         if row[i] == row[i-1] {
             row[i] *= 2;
@@ -442,16 +1269,18 @@ pub fn reduce_row_inplace(row: &mut LineView) -> i32 {
     add_score
 }
 
-// Defines row/column reduction rules. It assumes movement is "right", i.e. from 
-// index 0 towards index 3.
-pub fn reduce_row(row: [i32; 4]) -> ([i32; 4], i32) {
+// Defines row/column reduction rules for the standard merge rule (see
+// MergeRule::merge_tiles for the others). It assumes movement is "right", i.e.
+// from index 0 towards the last index.
+pub fn reduce_row<const N: usize>(row: [i32; N]) -> ([i32; N], i32) {
     let mut row = row;
+    let last = N - 1;
 
     // First, shift right as needed until there are no empty (value = 0) 
     // cells to the right of non-empty ones
     let mut i = 1;
     let mut add_score = 0;
-    while i <= 3 {
+    while i <= last {
         if row[i] != 0 {
             i += 1; 
             continue;
@@ -464,7 +1293,7 @@ pub fn reduce_row(row: [i32; 4]) -> ([i32; 4], i32) {
     }
 
     // Now combine matching neighbors, starting from the right
-    i = 3;
+    i = last;
     while i > 0 {
         if row[i] == row[i-1] {
             row[i] *= 2;
@@ -482,9 +1311,14 @@ pub fn reduce_row(row: [i32; 4]) -> ([i32; 4], i32) {
 #[cfg(test)]
 mod gameplay_tests {
     use super::*;
+
+    fn board(text: &str) -> Board {
+        text.parse().unwrap()
+    }
+
     #[test]
     fn board_row_access() {
-        let b = Board{ values: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], ..Board::default() };
+        let b = Board{ values: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], ..Board::default() };
         assert_eq!(b.row(0, false), [0, 1, 2, 3]);
         assert_eq!(b.row(2, false), [8, 9, 10, 11]);
         // Reversed
@@ -494,7 +1328,7 @@ mod gameplay_tests {
 
     #[test]
     fn board_col_access() {
-        let b = Board{ values: [0, 1, 2, 3, 
+        let b = Board{ values: vec![0, 1, 2, 3, 
                                 4, 5, 6, 7,
                                 8, 9, 10, 11,
                                 12, 13, 14, 15],
@@ -525,12 +1359,10 @@ mod gameplay_tests {
             if b1.values[i] == b2.values[i] {
                 continue;
             }
-            if b1.values[i] == 0 && change_count == 0 {
-                if b2.values[i] == 2 || b2.values[i] == 4 {
-                    // This is our only allowed change
-                    change_count = 1;
-                    continue;
-                }
+            if b1.values[i] == 0 && change_count == 0 && (b2.values[i] == 2 || b2.values[i] == 4) {
+                // This is our only allowed change
+                change_count = 1;
+                continue;
             }
             panic!("Bad match at {}\nb1: {:?}\n b2: {:?}", i, b1.values, b2.values);
         }
@@ -541,17 +1373,17 @@ mod gameplay_tests {
 
     #[test]
     fn test_play() {
-        let b = Board{ values: [0, 8, 0, 2, 
-                                4, 8, 2, 2,
-                                4, 8, 0, 0,
-                                8, 8, 0, 0],
-                       ..Board::default()};
-        let bdown_expected = Board{ values: [0, 0, 0, 0, 
-                                    0, 0, 0, 0,
-                                    8, 16, 0, 0,
-                                    8, 16, 2, 4],
-                                    ..Board::default() };
-        let mut player = GamePlayer::default();
+        let b = board("
+            . 8 . 2
+            4 8 2 2
+            4 8 . .
+            8 8 . .");
+        let bdown_expected = board("
+             .  .  .  .
+             .  .  .  .
+             8 16  .  .
+             8 16  2  4");
+        let mut player = GamePlayer::from_seed(3);
         let bdown = player.play(&b, MoveDir::Down);
         match bdown {
             Ok(bdown) => board_compare(bdown_expected, bdown),
@@ -559,31 +1391,500 @@ mod gameplay_tests {
         }
 
         // Play returns error on invalid move
-        let b = Board{ values: [0, 2, 4, 8, 
-                                0, 2, 4, 8,
-                                0, 2, 4, 8,
-                                0, 2, 4, 8],
-                        ..Board::default() };
+        let b = board("
+            . 2 4 8
+            . 2 4 8
+            . 2 4 8
+            . 2 4 8");
         let bbad = player.play(&b, MoveDir::Right);
         assert!(bbad.is_err(), "Right should be an invalid move");
     }
 
+    #[test]
+    fn test_errors() {
+        let mut player = GamePlayer::from_seed(7);
+        let b = board("
+            . 2 4 8
+            . 2 4 8
+            . 2 4 8
+            . 2 4 8");
+        assert_eq!(player.play(&b, MoveDir::Right).err(), Some(GameError::NoOpMove(MoveDir::Right)));
+
+        let mut b = board("
+              2   8  16  32
+            256  16   2  16
+              4   8   4   8
+              2   4   2   4");
+        assert_eq!(player.play_inplace(&mut b, MoveDir::Up), Err(GameError::GameOver));
+        assert_eq!(player.spawn(&mut b), Err(GameError::BoardFull));
+
+        let b = Board{ values: vec![0, 3, 0, 0, 
+                                0, 0, 0, 0,
+                                0, 0, 0, 0,
+                                0, 0, 0, 0],
+                        ..Board::default() };
+        assert_eq!(player.play(&b, MoveDir::Left).err(), Some(GameError::InvalidBoard{cell: 1, value: 3}));
+
+        // A successful move reports what it did
+        let mut b = board("
+            2 2 . .
+            . . . .
+            . . . .
+            . . . .");
+        let result = player.play_inplace(&mut b, MoveDir::Left).unwrap();
+        assert_eq!(result.score, 4);
+        assert_eq!(result.spawned.len(), 1);
+        assert_eq!(b.values[result.spawned[0].cell], result.spawned[0].value);
+        assert_eq!(b.values[0], 4);
+    }
+
+    #[test]
+    fn test_game_status() {
+        let b = board("
+            4 4 . .
+            . . . .
+            . . . .
+            . . . .");
+        let options = GameOptions{target: 8, ..GameOptions::default()};
+        let mut game = Game::with_board(b.clone(), GamePlayer::from_seed(1), options);
+        assert_eq!(game.status(), GameStatus::InProgress);
+        assert_eq!(game.play(MoveDir::Right).err(), None);
+        assert_eq!(game.moves(), 1);
+        assert_eq!(game.status(), GameStatus::Won);
+        assert!(game.has_won());
+        assert_eq!(game.play(MoveDir::Left), Err(GameError::GameOver));
+        assert_eq!(game.moves(), 1);
+
+        // Keep playing after the win until stuck, which still counts as a win
+        let options = GameOptions{target: 8, keep_playing: true, ..GameOptions::default()};
+        let mut game = Game::with_board(b, GamePlayer::from_seed(1), options);
+        game.play(MoveDir::Right).unwrap();
+        assert_eq!(game.status(), GameStatus::InProgress);
+        assert!(game.has_won());
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut turn = 0;
+        while game.status() == GameStatus::InProgress {
+            let _ = game.play(dirs[turn % 4]);
+            turn += 1;
+        }
+        assert_eq!(game.status(), GameStatus::Won);
+
+        let stuck = board("
+              2   8  16  32
+            256  16   2  16
+              4   8   4   8
+              2   4   2   4");
+        let game = Game::with_board(stuck, GamePlayer::from_seed(1), GameOptions::default());
+        assert_eq!(game.status(), GameStatus::Lost);
+    }
+
+    #[test]
+    fn test_slide_then_spawn() {
+        let b = board("
+            . 8 . 2
+            4 8 2 2
+            4 8 . .
+            8 8 . .");
+        let (after, add_score) = b.slide(MoveDir::Down).unwrap();
+        assert_eq!(after.values, [0, 0, 0, 0, 
+                                  0, 0, 0, 0,
+                                  8, 16, 0, 0,
+                                  8, 16, 2, 4]);
+        assert_eq!(add_score, 44);
+        assert_eq!(after.score, 44);
+
+        let mut spawned = after.clone();
+        spawned.spawn(5, 4).unwrap();
+        assert_eq!(spawned.values[5], 4);
+        assert_eq!(spawned.spawn(5, 2), Err(GameError::CellUnavailable(5)));
+        assert_eq!(spawned.spawn(16, 2), Err(GameError::CellUnavailable(16)));
+        assert_eq!(spawned.spawn(6, 3), Err(GameError::InvalidBoard{cell: 6, value: 3}));
+
+        let mut player = GamePlayer::from_seed(11);
+        let Spawn{cell, value} = player.spawn(&mut spawned).unwrap();
+        assert_eq!(after.values[cell], 0);
+        assert!(value == 2 || value == 4);
+
+        // Sliding with nothing to move is an error, and leaves the board alone
+        let b = board("
+            . 2 4 8
+            . 2 4 8
+            . 2 4 8
+            . 2 4 8");
+        let mut stuck = b.clone();
+        assert!(slide_inplace(&mut stuck, MoveDir::Right).is_err());
+        assert_eq!(stuck.values, b.values);
+    }
+
+    #[test]
+    fn test_init() {
+        let mut player = GamePlayer::from_seed(99);
+        for _ in 0..20 {
+            let b = player.new_board(StartPosition::Random);
+            let tiles: Vec<i32> = b.values.iter().cloned().filter(|v| *v != 0).collect();
+            assert_eq!(tiles.len(), 2);
+            assert!(tiles.iter().all(|v| *v == 2 || *v == 4));
+            assert_eq!(b.score, 0);
+        }
+        let b = player.new_board(StartPosition::Fixed);
+        assert_eq!(b.values, Board::init_fixed(4, 4).values);
+    }
+
+    #[test]
+    fn test_spawn_rules() {
+        let rules = SpawnRules{values: vec![(2, 1), (8, 0), (16, 3)], tiles_per_move: 3, start_tiles: 5};
+        assert!(rules.is_valid(MergeRule::Standard));
+        assert_eq!(rules.probabilities(), vec![(2, 0.25), (16, 0.75)]);
+        assert!(!SpawnRules{values: vec![(2, 0)], ..SpawnRules::default()}.is_valid(MergeRule::Standard));
+        assert!(!SpawnRules{values: vec![(6, 1)], ..SpawnRules::default()}.is_valid(MergeRule::Standard));
+        assert!(!SpawnRules{tiles_per_move: 0, ..SpawnRules::default()}.is_valid(MergeRule::Standard));
+        assert!(SpawnRules{values: vec![(6, 1)], ..SpawnRules::default()}.is_valid(MergeRule::SumEqual));
+        for merge in MergeRule::ALL.iter() {
+            assert!(SpawnRules::for_merge(*merge).is_valid(*merge));
+        }
+        for values in [vec![], vec![(2, 0), (4, 0)]].iter() {
+            let rules = SpawnRules{values: values.clone(), ..SpawnRules::default()};
+            assert_eq!(GamePlayer::from_seed(5).with_rules(rules, MergeRule::Standard).err(), Some(GameError::InvalidSpawnRules));
+        }
+        // Spawns have to be tiles of the game they're spawned in
+        let sixes = SpawnRules{values: vec![(6, 1)], ..SpawnRules::default()};
+        assert_eq!(GamePlayer::from_seed(5).with_rules(sixes.clone(), MergeRule::Standard).err(), Some(GameError::InvalidSpawnRules));
+        let fibonacci = GameOptions{merge: MergeRule::Fibonacci, ..GameOptions::default()};
+        assert_eq!(Game::new(GamePlayer::from_seed(5), fibonacci).err(), Some(GameError::InvalidSpawnRules));
+        let mut b = Board::new(4, 4);
+        let spawned = spawn_random(&mut b, &mut GamePlayer::from_seed(5).rng, &sixes);
+        assert!(matches!(spawned, Err(GameError::InvalidBoard{value: 6, ..})));
+        assert_eq!(b, Board::new(4, 4));
+
+        let mut player = GamePlayer::from_seed(5).with_rules(rules, MergeRule::Standard).unwrap();
+        let mut b = player.new_board(StartPosition::Random);
+        assert_eq!(b.values.iter().filter(|v| **v != 0).count(), 5);
+        let mut counts = [0; 2];
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut turn = 0;
+        while !b.stuck() {
+            let empty_after_slide = match b.slide(dirs[turn % 4]) {
+                Ok((after, _)) => after.values.iter().filter(|v| **v == 0).count(),
+                Err(_) => {
+                    turn += 1;
+                    continue;
+                },
+            };
+            let result = player.play_inplace(&mut b, dirs[turn % 4]).unwrap();
+            // Three tiles at a time, unless the board fills up first
+            assert_eq!(result.spawned.len(), empty_after_slide.min(3));
+            for spawn in &result.spawned {
+                assert_eq!(b.values[spawn.cell], spawn.value);
+                counts[(spawn.value == 16) as usize] += 1;
+            }
+            turn += 1;
+        }
+        assert!(counts[1] > counts[0]);
+    }
+
+    #[test]
+    fn seeded_players_repeat() {
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut p1 = GamePlayer::from_seed(1234);
+        let mut p2 = GamePlayer::from_seed(1234);
+        let mut b1 = p1.new_board(StartPosition::Random);
+        let mut b2 = p2.new_board(StartPosition::Random);
+        let mut turn = 0;
+        while !b1.stuck() {
+            let r1 = p1.play_inplace(&mut b1, dirs[turn % 4]);
+            let r2 = p2.play_inplace(&mut b2, dirs[turn % 4]);
+            assert_eq!(r1, r2);
+            assert_eq!(b1.values, b2.values);
+            turn += 1;
+        }
+    }
+
+    #[test]
+    fn packed_and_cell_moves_agree() {
+        // The table driven moves used for ordinary boards must give exactly
+        // the same result as the cell by cell fallback
+        let mut player = GamePlayer::from_seed(13);
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        for game in 0..20 {
+            let mut b = player.new_board(StartPosition::Random);
+            let mut turn = game;
+            while !b.stuck() {
+                for dir in dirs.iter() {
+                    let bits = b.to_bitboard().unwrap();
+                    let (moved, add_score) = bits.slide(*dir);
+                    let mut slow = b.clone();
+                    let changed = slide_values(&mut slow, *dir);
+                    assert_eq!(moved.to_values().to_vec(), slow.values);
+                    assert_eq!(b.score + add_score, slow.score);
+                    assert_eq!(changed, moved != bits);
+                    assert_eq!(changed, b.is_valid_move(*dir));
+                }
+                turn += 1;
+                let _ = player.play_inplace(&mut b, dirs[turn % 4]);
+            }
+        }
+    }
+
     #[test]
     fn test_stuck() {
-        let b = Board{ 
-            values: [0, 8, 0, 2, 
-                     4, 8, 2, 2,
-                     4, 8, 0, 0,
-                     8, 8, 0, 0],
-            ..Board::default() };
-        assert_eq!(b.stuck(), false);
-
-        let b = Board{ 
-            values: [2, 8, 16, 32, 
-                     256, 16, 2, 16,
-                     4, 8, 4, 8,
-                     2, 4, 2, 4],
-            ..Board::default() };
-        assert_eq!(b.stuck(), true);
+        let b = board("
+            . 8 . 2
+            4 8 2 2
+            4 8 . .
+            8 8 . .");
+        assert!(!b.stuck());
+
+        let b = board("
+              2   8  16  32
+            256  16   2  16
+              4   8   4   8
+              2   4   2   4");
+        assert!(b.stuck());
+    }
+
+    #[test]
+    fn empty_cells_are_not_a_move() {
+        // Two neighbouring empty cells used to count as a pair of equal tiles
+        // which could merge, so any board with a gap looked movable every way.
+        // A 65536 doesn't pack, so that board goes cell by cell.
+        // Boards of other sizes always go cell by cell.
+        let boards = [
+            board("2,.,.,./.,.,.,./.,.,.,./.,.,.,."),
+            board("65536,.,.,./.,.,.,./.,.,.,./.,.,.,."),
+            board("2,.,.,.,./.,.,.,.,./.,.,.,.,."),
+        ];
+        for b in boards.iter() {
+            assert!(!b.is_valid_move(MoveDir::Left));
+            assert!(!b.is_valid_move(MoveDir::Up));
+            assert!(b.is_valid_move(MoveDir::Right));
+            assert!(b.is_valid_move(MoveDir::Down));
+        }
+    }
+
+    #[test]
+    fn other_sizes() {
+        let b = board("
+            2 2 4
+            . . 4
+            8 . 8");
+        let (after, add_score) = b.slide(MoveDir::Right).unwrap();
+        assert_eq!(after.to_compact(), ".,4,4/.,.,4/.,.,16");
+        assert_eq!(add_score, 20);
+        let (after, _) = b.slide(MoveDir::Up).unwrap();
+        assert_eq!(after.to_compact(), "2,2,8/8,.,8/.,.,.");
+
+        let b = board("
+            2 . 2 2 4
+            4 4 8 . 8");
+        assert_eq!((b.rows, b.cols), (2, 5));
+        let (after, add_score) = b.slide(MoveDir::Left).unwrap();
+        assert_eq!(after.to_compact(), "4,2,4,.,./8,16,.,.,.");
+        assert_eq!(add_score, 28);
+        assert_eq!(b.col(1, true), vec![4, 0]);
+
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut player = GamePlayer::from_seed(21);
+        for (rows, cols) in [(3, 3), (5, 5), (6, 6), (4, 6), (2, 5)].iter() {
+            let mut b = player.new_board_of_size(StartPosition::Random, *rows, *cols);
+            assert_eq!(b.values.iter().filter(|v| **v != 0).count(), 2);
+            let mut turn = 0;
+            while !b.stuck() {
+                for dir in dirs.iter() {
+                    assert_eq!(b.is_valid_move(*dir), b.slide(*dir).is_ok());
+                }
+                turn += 1;
+                let _ = player.play_inplace(&mut b, dirs[turn % 4]);
+            }
+            assert_eq!(board(&b.to_string()).values, b.values);
+            assert!(b.score > 0);
+        }
+    }
+
+    #[test]
+    fn packed_form_follows_changes() {
+        let mut b = board("
+            2 . . .
+            . 4 . .
+            . . 8 .
+            . . . .");
+        let packed = |b: &Board| Board::with_values(4, 4, b.values().to_vec(), MergeRule::Standard).to_bitboard();
+        assert_eq!(b.to_bitboard(), packed(&b));
+        b.set(3, 16);
+        assert_eq!(b.to_bitboard(), packed(&b));
+        b.set(3, 3);
+        assert_eq!(b.to_bitboard(), None);
+        b.set(3, 0);
+        assert_eq!(b.to_bitboard(), packed(&b));
+        b.values_mut()[15] = 32;
+        assert_eq!(b.to_bitboard(), packed(&b));
+    }
+
+    #[test]
+    fn largest_tiles_merge_however_reached() {
+        // Merging two 16384s leaves 32768s, which the tables can't merge, so
+        // the board that move leaves has to go cell by cell just like one
+        // which held them from the start
+        let (mut b, _) = board("
+            16384 16384 . .
+            16384 16384 . .
+                .     . . .
+                .     . . .").slide(MoveDir::Left).unwrap();
+        let parsed = board("32768,.,.,./32768,.,.,./.,.,.,./.,.,.,.");
+        assert_eq!(b.values(), parsed.values());
+        assert_eq!(b.to_bitboard(), None);
+        for dir in [MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right].iter() {
+            assert_eq!(b.is_valid_move(*dir), parsed.is_valid_move(*dir));
+        }
+        let mut player = GamePlayer::default();
+        player.play_inplace(&mut b, MoveDir::Up).unwrap();
+        assert_eq!(b.values()[0], 65536);
+    }
+
+    #[test]
+    fn merge_rules() {
+        let b = Board::parse("1,1,2,3/2,3,5,.", MergeRule::Fibonacci).unwrap();
+        let (after, add_score) = b.slide(MoveDir::Left).unwrap();
+        assert_eq!(after.to_compact(), "2,5,.,./5,5,.,.");
+        assert_eq!(add_score, 12);
+        assert!(b.to_bitboard().is_none());
+        assert!("1,1,2,3/2,3,5,.".parse::<Board>().is_err());
+
+        let b = Board::parse("3,3,3/9,9,27/3,9,27", MergeRule::PowersOfThree).unwrap();
+        assert!(b.is_valid_move(MoveDir::Right));
+        assert!(!b.is_valid_move(MoveDir::Up));
+        assert!(!b.stuck());
+        let (after, add_score) = b.slide(MoveDir::Right).unwrap();
+        assert_eq!(after.to_compact(), ".,.,9/9,9,27/3,9,27");
+        assert_eq!(add_score, 9);
+        assert!(!after.is_valid_move(MoveDir::Right));
+        assert_eq!(b.slide(MoveDir::Down).err(), Some(GameError::NoOpMove(MoveDir::Down)));
+
+        // Equal tiles still merge in pairs when any value is a tile
+        let b = Board::parse("6,6,6,7/.,.,.,./.,.,.,./.,.,.,.", MergeRule::SumEqual).unwrap();
+        assert_eq!(b.slide(MoveDir::Right).unwrap().0.row(0, false), vec![0, 6, 12, 7]);
+        let mut spawned = b.clone();
+        assert_eq!(spawned.spawn(4, 5), Ok(()));
+        assert!(Board::init_fixed(4, 4).spawn(4, 5).is_err());
+
+        // Games under each rule stay valid, and spawn only their own tiles
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        for merge in MergeRule::ALL.iter() {
+            let options = GameOptions{merge: *merge, keep_playing: true, ..GameOptions::default()};
+            let mut game = Game::new(GamePlayer::from_seed(17).with_rules(SpawnRules::for_merge(*merge), *merge).unwrap(), options).unwrap();
+            let mut turn = 0;
+            while game.status() == GameStatus::InProgress {
+                for dir in dirs.iter() {
+                    assert_eq!(game.board().is_valid_move(*dir), game.board().slide(*dir).is_ok());
+                }
+                turn += 1;
+                let _ = game.play(dirs[turn % 4]);
+            }
+            assert_eq!(game.board().merge, *merge);
+            assert_eq!(game.board().validate(), Ok(()));
+            assert!(game.board().stuck());
+        }
+        let fixed = GameOptions{merge: MergeRule::Fibonacci, start: StartPosition::Fixed, ..GameOptions::default()};
+        assert_eq!(GamePlayer::from_seed(1).new_game_board(&fixed).values()[0], 1);
+    }
+
+    #[test]
+    fn blockers_and_wildcards() {
+        // Tiles stop at blockers, and each side of one moves on its own
+        let b = board("2,#,.,2/.,4,#,4/*,8,.,./.,.,.,.");
+        assert_eq!(b.to_compact(), "2,#,.,2/.,4,#,4/*,8,.,./.,.,.,.");
+        assert_eq!(format!("{}", b).lines().next(), Some("2 # . 2"));
+        assert_eq!(b.validate(), Ok(()));
+        let (after, add_score) = b.slide(MoveDir::Left).unwrap();
+        assert_eq!(after.to_compact(), "2,#,2,./4,.,#,4/16,.,.,./.,.,.,.");
+        assert_eq!(add_score, 16);
+        // A wildcard merges like a copy of the tile it meets
+        let (after, add_score) = b.slide(MoveDir::Up).unwrap();
+        assert_eq!(after.to_compact(), "4,#,.,2/.,4,#,4/.,8,.,./.,.,.,.");
+        assert_eq!(add_score, 4);
+        assert!(b.is_valid_move(MoveDir::Right));
+
+        // Blockers don't move, so a move only against them does nothing
+        let b = board("#,2,#,4/.,.,.,./.,.,.,./.,.,.,.");
+        assert!(!b.is_valid_move(MoveDir::Up));
+        assert_eq!(b.slide(MoveDir::Up).err(), Some(GameError::NoOpMove(MoveDir::Up)));
+
+        // An empty cell walled off by blockers doesn't help, and two
+        // wildcards have nothing to copy
+        assert!(board("*,#/#,./2,#").stuck());
+        assert!(!board("*,#/#,./*,2").stuck());
+        assert!(board("*,*/#,#").stuck());
+
+        // Games keep their blockers where they started
+        let options = GameOptions{blockers: 3, wildcards: 2, keep_playing: true, ..GameOptions::default()};
+        let mut game = Game::new(GamePlayer::from_seed(9), options).unwrap();
+        let blocked: Vec<usize> = (0..16).filter(|cell| game.board().values[*cell] == BLOCKER).collect();
+        assert_eq!(blocked.len(), 3);
+        assert_eq!(game.board().values.iter().filter(|v| **v == WILDCARD).count(), 2);
+        assert!(game.board().to_bitboard().is_none());
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut turn = 0;
+        while game.status() == GameStatus::InProgress {
+            for dir in dirs.iter() {
+                assert_eq!(game.board().is_valid_move(*dir), game.board().slide(*dir).is_ok());
+            }
+            turn += 1;
+            let _ = game.play(dirs[turn % 4]);
+            assert!(blocked.iter().all(|cell| game.board().values[*cell] == BLOCKER));
+        }
+        assert_eq!(game.board().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_text_format() {
+        let b = board("
+            128   2   2   8
+            256   8  16   8
+            256   8   .   .
+             64  32   .   .");
+        assert_eq!(*b.values(), [128, 2, 2, 8, 256, 8, 16, 8, 256, 8, 0, 0, 64, 32, 0, 0]);
+        assert_eq!(b.to_string(), "128   2   2   8\n256   8  16   8\n256   8   .   .\n 64  32   .   .");
+        assert_eq!(b.to_compact(), "128,2,2,8/256,8,16,8/256,8,.,./64,32,.,.");
+        assert_eq!(format!("{:?}", b), "Board { values: 128,2,2,8/256,8,16,8/256,8,.,./64,32,.,., score: 0 }");
+
+        // Both forms read back to the same board
+        assert_eq!(board(&b.to_string()).values(), b.values());
+        assert_eq!(board(&b.to_compact()).values(), b.values());
+        assert_eq!(board("0 2 0 0\n0 0 0 0\n0 0 0 0\n0 0 0 4").values(), board("., 2,.,./.,.,.,./.,.,.,./.,.,., 4").values());
+
+        assert_eq!("\n\n".parse::<Board>().err(), Some(ParseBoardError::Empty));
+        assert_eq!("2,.,.,./.,.,./.,.,.,./.,.,.,.".parse::<Board>().err(),
+            Some(ParseBoardError::RowLength{row: 1, len: 3, expected: 4}));
+        assert_eq!("2,.,.,./.,x,.,./.,.,.,./.,.,.,.".parse::<Board>().err(),
+            Some(ParseBoardError::BadCell{row: 1, col: 1, text: "x".to_string()}));
+        assert_eq!("2,.,.,./.,.,.,./.,.,6,./.,.,.,.".parse::<Board>().err(),
+            Some(ParseBoardError::InvalidTile{row: 2, col: 2, value: 6, merge: MergeRule::Standard}));
+        assert_eq!("1,.,.,./.,.,.,./.,.,.,./.,.,.,.".parse::<Board>().err(),
+            Some(ParseBoardError::InvalidTile{row: 0, col: 0, value: 1, merge: MergeRule::Standard}));
+        // Tiles are checked against the board's own merge rule
+        let error = Board::parse("1,2,4/.,.,./.,.,.", MergeRule::Fibonacci).unwrap_err();
+        assert_eq!(error.to_string(), "Cell 2 of row 0 is 4, which is not a valid tile for the fibonacci merge rule");
+    }
+
+    #[test]
+    fn saved_boards_are_checked() {
+        let mut b = Board::parse("1,2,./3,.,./.,.,8", MergeRule::Fibonacci).unwrap();
+        b.score = 12;
+        let read: Board = serde_json::from_str(&serde_json::to_string(&b).unwrap()).unwrap();
+        assert_eq!(read, b);
+        assert_eq!((read.rows(), read.cols()), (3, 3));
+        // Older boards were always 4x4, with standard tiles
+        let old: Board = serde_json::from_str(r#"{"values": [2,0,0,0, 0,0,0,0, 0,0,0,0, 0,0,0,4], "score": 0}"#).unwrap();
+        assert_eq!(old, board("2,.,.,./.,.,.,./.,.,.,./.,.,.,4"));
+
+        // Boards which the values don't fill, or which hold values that aren't
+        // tiles, are errors rather than panics later on
+        for text in &[
+            r#"{"rows": 3, "cols": 3, "values": [2,0,0,0], "score": 0}"#,
+            r#"{"rows": 0, "cols": 0, "values": [], "score": 0}"#,
+            r#"{"values": [2,0,0,0, 0,0,0,0, 0,0,0,0, 0,0,0,3], "score": 0}"#,
+            r#"{"rows": 1, "cols": 2, "values": [2,4], "score": 0, "merge": "Fibonacci"}"#,
+        ] {
+            assert!(serde_json::from_str::<Board>(text).is_err(), "{}", text);
+        }
     }
 }
\ No newline at end of file
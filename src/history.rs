@@ -0,0 +1,170 @@
+use super::gameplay::*;
+
+use serde::{Serialize, Deserialize};
+
+// One position in a game, along with the move that led to it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // The board (and score) after the move
+    pub board: Board,
+    // None for the starting position
+    pub dir: Option<MoveDir>,
+    // The tiles added after the move, empty for the starting position
+    pub spawned: Vec<Spawn>,
+}
+
+// A game which remembers every position it has been through, so that moves can
+// be undone and redone. Making a move after undoing starts a new branch from
+// that position, and discards the moves that had been undone.
+pub struct HistoryGame {
+    game: Box<dyn PlayedGame>,
+    entries: Vec<HistoryEntry>,
+    // Index of the entry for the current position
+    current: usize,
+}
+
+impl HistoryGame {
+    pub fn new(player: GamePlayer, options: GameOptions) -> Result<HistoryGame, GameError> {
+        Ok(HistoryGame::from_game(Game::new(player, options)?))
+    }
+
+    // Start recording from the game's current position. The game can be any
+    // kind, such as a ThreesGame dealing from its deck.
+    pub fn from_game<G: PlayedGame + 'static>(game: G) -> HistoryGame {
+        let start = HistoryEntry{board: game.game().board().clone(), dir: None, spawned: Vec::new()};
+        HistoryGame{game: Box::new(game), entries: vec![start], current: 0}
+    }
+
+    pub fn game(&self) -> &Game {
+        self.game.game()
+    }
+
+    pub fn board(&self) -> &Board {
+        self.game.game().board()
+    }
+
+    // Every recorded position, including any that have been undone
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    // Index into entries() of the current position
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    pub fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        let result = self.game.play(dir)?;
+        self.entries.truncate(self.current + 1);
+        self.entries.push(HistoryEntry{
+            board: self.game.game().board().clone(),
+            dir: Some(dir),
+            spawned: result.spawned.clone(),
+        });
+        self.current += 1;
+        Ok(result)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.current + 1 < self.entries.len()
+    }
+
+    // Step back one move. Returns false if already at the start.
+    pub fn undo(&mut self) -> bool {
+        self.can_undo() && self.goto(self.current - 1)
+    }
+
+    // Step forward again over an undone move, putting back the same tiles that
+    // spawned the first time. Returns false if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        self.can_redo() && self.goto(self.current + 1)
+    }
+
+    // Jump to any recorded position. Returns false if there is no such entry.
+    pub fn goto(&mut self, position: usize) -> bool {
+        match self.entries.get(position) {
+            Some(entry) => {
+                self.game.restore(entry.board.clone(), position as i32);
+                self.current = position;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+
+    fn new_game() -> HistoryGame {
+        HistoryGame::new(GamePlayer::from_seed(5), GameOptions::default()).unwrap()
+    }
+
+    fn play_some(game: &mut HistoryGame, count: usize) {
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut turn = 0;
+        let mut played = 0;
+        while played < count {
+            if game.play(dirs[turn % 4]).is_ok() {
+                played += 1;
+            }
+            turn += 1;
+        }
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut game = new_game();
+        assert!(!game.undo());
+        assert!(!game.redo());
+        let start = game.board().clone();
+        play_some(&mut game, 5);
+        let end = game.board().clone();
+        assert_eq!(game.entries().len(), 6);
+
+        for _ in 0..5 {
+            assert!(game.undo());
+        }
+        assert!(!game.undo());
+        assert_eq!(game.board().values(), start.values());
+        assert_eq!(game.board().score, 0);
+        assert_eq!(game.game().moves(), 0);
+
+        for _ in 0..5 {
+            assert!(game.redo());
+        }
+        assert!(!game.redo());
+        assert_eq!(game.board().values(), end.values());
+        assert_eq!(game.board().score, end.score);
+        assert_eq!(game.game().moves(), 5);
+
+        // Each entry records the move and tile that produced it
+        for entry in &game.entries()[1..] {
+            assert!(entry.dir.is_some());
+            assert_eq!(entry.spawned.len(), 1);
+            let spawned = entry.spawned[0];
+            assert_eq!(entry.board.values()[spawned.cell], spawned.value);
+        }
+    }
+
+    #[test]
+    fn branch() {
+        let mut game = new_game();
+        play_some(&mut game, 6);
+        assert!(game.goto(2));
+        assert_eq!(game.position(), 2);
+        assert!(game.can_redo());
+        play_some(&mut game, 1);
+        // The moves after position 2 were replaced by the new one
+        assert_eq!(game.entries().len(), 4);
+        assert_eq!(game.position(), 3);
+        assert!(!game.can_redo());
+        assert_eq!(game.game().moves(), 3);
+        assert!(!game.goto(4));
+    }
+}
@@ -3,17 +3,20 @@ extern crate easycurses;
 use easycurses::*;
 use easycurses::constants::acs;
 
-use super::*;
-use super::gameplay::{GamePlayer, MoveDir, Board};
+use super::gameplay::{BLOCKER, WILDCARD, Board, Game, GameOptions, GamePlayer, GameStatus, MoveDir, SpawnRules};
+use super::history::HistoryGame;
+use super::MergeRule;
+use super::threes::ThreesGame;
+use super::record::GameRecord;
+use super::algorithm::Strategy;
 
 const CELL_WIDTH:i32 = 10;
 const CELL_HEIGHT:i32 = 6;
-const WIDTH:i32 = CELL_WIDTH * 4 + 1;
-const HEIGHT:i32 = CELL_HEIGHT * 4 + 1;
+const SAVE_FILE: &str = "game.yml";
 
-fn draw_topline(easy: &mut EasyCurses) {
+fn draw_topline(easy: &mut EasyCurses, width: i32) {
     easy.print_char(acs::ulcorner());
-    for i in 1..(WIDTH-1) {
+    for i in 1..(width-1) {
         if ((i)%CELL_WIDTH) == 0 {
             easy.print_char(acs::ttee());
         } else {
@@ -23,9 +26,9 @@ fn draw_topline(easy: &mut EasyCurses) {
     easy.print_char(acs::urcorner());
 }
 
-fn draw_vsep(easy: &mut EasyCurses) {
+fn draw_vsep(easy: &mut EasyCurses, width: i32) {
     easy.print_char(acs::ltee());
-    for i in 1..(WIDTH-1) {
+    for i in 1..(width-1) {
         if (i)%CELL_WIDTH == 0 {
             easy.print_char(acs::plus());
         } else {
@@ -35,9 +38,9 @@ fn draw_vsep(easy: &mut EasyCurses) {
     easy.print_char(acs::rtee());    
 }
 
-fn draw_vblank(easy: &mut EasyCurses) {
+fn draw_vblank(easy: &mut EasyCurses, width: i32) {
     easy.print_char(acs::vline());
-        for i in 1..(WIDTH-1) {
+        for i in 1..(width-1) {
         if (i)%CELL_WIDTH == 0 {
             easy.print_char(acs::vline());
         } else {
@@ -47,9 +50,9 @@ fn draw_vblank(easy: &mut EasyCurses) {
     easy.print_char(acs::vline());    
 }
 
-fn draw_botline(easy: &mut EasyCurses) {
+fn draw_botline(easy: &mut EasyCurses, width: i32) {
     easy.print_char(acs::llcorner());
-    for i in 1..(WIDTH-1) {
+    for i in 1..(width-1) {
         if ((i)%CELL_WIDTH) == 0 {
             easy.print_char(acs::btee());
         } else {
@@ -59,8 +62,51 @@ fn draw_botline(easy: &mut EasyCurses) {
     easy.print_char(acs::lrcorner());
 }
 
-pub fn run(algo: fn(&mut GamePlayer, &Board) -> MoveDir) {
-    let mut board = gameplay::Board::init();
+// Shade the inside of a blocked cell
+fn draw_blocker(easy: &mut EasyCurses, r: i32, c: i32) {
+    for line in 1..CELL_HEIGHT {
+        easy.move_rc(r * CELL_HEIGHT + line, c * CELL_WIDTH + 1);
+        for _ in 1..CELL_WIDTH {
+            easy.print_char(acs::ckboard());
+        }
+    }
+}
+
+// Save the game up to the current position. The interactive player isn't
+// seeded, so the record keeps the spawned tiles but no seed.
+fn save(game: &HistoryGame) -> String {
+    let saved = GameRecord::from_history(game, None).to_yaml()
+        .map_err(|e| e.to_string())
+        .and_then(|yaml| std::fs::write(SAVE_FILE, yaml).map_err(|e| e.to_string()));
+    match saved {
+        Ok(()) => format!("Saved to {}", SAVE_FILE),
+        Err(e) => format!("Could not save: {}", e),
+    }
+}
+
+// Returns true if the move was made
+fn try_play(game: &mut HistoryGame, dir: MoveDir, message: &mut String) -> bool {
+    match game.play(dir) {
+        Ok(_) => true,
+        Err(error) => {
+            *message = error.to_string();
+            false
+        },
+    }
+}
+
+// Play from the given position, or from a new game if there is none
+pub fn run(strategy: &mut dyn Strategy, options: GameOptions, start: Option<Board>) {
+    // Spawn the usual tiles for the game's merge rule
+    let new_player = || GamePlayer::default().with_rules(SpawnRules::for_merge(options.merge), options.merge).expect("usual spawn rules are valid");
+    let mut game = match start {
+        // Threes tiles are dealt from its deck, as in the real game
+        Some(board) if options.merge == MergeRule::Threes => HistoryGame::from_game(ThreesGame::with_board(board, rand::random(), options)),
+        None if options.merge == MergeRule::Threes => HistoryGame::from_game(ThreesGame::from_seed(rand::random(), options)),
+        Some(board) => HistoryGame::from_game(Game::with_board(board, new_player(), options)),
+        None => HistoryGame::new(new_player(), options).expect("usual spawn rules are valid"),
+    };
+    let mut hint_player = new_player();
     let mut message = String::new();
     // Common startup
     let mut easy = EasyCurses::initialize_system().unwrap();
@@ -68,42 +114,56 @@ pub fn run(algo: fn(&mut GamePlayer, &Board) -> MoveDir) {
     easy.set_echo(false);
     easy.set_keypad_enabled(true);
 
-    let mut suggested_move = MoveDir::Down;
+    strategy.reset();
+    let mut suggested_move = strategy.choose(&mut hint_player, game.board());
 
     loop {
+        let (rows, cols) = (game.board().rows() as i32, game.board().cols() as i32);
+        let width = CELL_WIDTH * cols + 1;
+        let height = CELL_HEIGHT * rows + 1;
         easy.clear();
 
         easy.move_rc(0, 0);
-        draw_topline(&mut easy);
+        draw_topline(&mut easy, width);
 
-        for line in 1..(HEIGHT-1) {
-            easy.move_rc(line as i32, 0);
+        for line in 1..(height-1) {
+            easy.move_rc(line, 0);
             if (line%CELL_HEIGHT) == 0 {
-                draw_vsep(&mut easy);
+                draw_vsep(&mut easy, width);
             } else {
-                draw_vblank(&mut easy);
+                draw_vblank(&mut easy, width);
             }
         }
 
-        easy.move_rc(HEIGHT-1, 0);
+        easy.move_rc(height-1, 0);
         
-        draw_botline(&mut easy);
+        draw_botline(&mut easy, width);
 
-        for r in 0..4 {
-            let row = board.row(r as usize, false);
-            for c in 0..4 {
+        for r in 0..rows {
+            let row = game.board().row(r as usize, false);
+            for c in 0..cols {
                 easy.move_rc(
                     r * CELL_HEIGHT + CELL_HEIGHT / 2,
                     c * CELL_WIDTH + CELL_WIDTH / 2);
-                let value = row[c as usize];
-                if value > 0 {
-                    easy.print(value.to_string());
+                match row[c as usize] {
+                    BLOCKER => draw_blocker(&mut easy, r, c),
+                    WILDCARD => {
+                        easy.set_bold(true);
+                        easy.print("*");
+                        easy.set_bold(false);
+                    },
+                    value if value > 0 => {
+                        easy.print(value.to_string());
+                    },
+                    _ => {},
                 }
             }
         }
 
-        easy.move_rc(HEIGHT+1, 0);
+        easy.move_rc(height+1, 0);
         easy.print(&message);
+        easy.move_rc(height+2, 0);
+        easy.print("Arrow keys to move, u to undo, r to redo, s to save");
 
         easy.refresh();
 
@@ -112,36 +172,44 @@ pub fn run(algo: fn(&mut GamePlayer, &Board) -> MoveDir) {
         easy.move_rc(0, 0);
         easy.insert_line();
         easy.insert_line();
-        easy.print(format!("Score: {}", &board.score));
+        let status = match game.game().status() {
+            GameStatus::Lost => "Game over! (u to undo)".to_string(),
+            _ if game.game().has_won() => format!("You reached {}!", game.game().options().target),
+            _ => String::new(),
+        };
+        easy.print(format!("Score: {}  Moves: {}  {}", game.board().score, game.game().moves(), status));
         easy.move_rc(1, 0);
         let move_str = match &suggested_move {
-            MoveDir::Up => "Up",
-            MoveDir::Down => "Down",
-            MoveDir::Left => "Left",
-            MoveDir::Right => "Right",
-        };
-        easy.print(format!("Suggested: {}", move_str));
-
-
-        let mut try_play = |dir: MoveDir| {
-            let mut player = GamePlayer::default();
-            let result = player.play(&board, dir);
-            match result {
-                Ok(new_board) => {
-                    board = new_board;
-                    suggested_move = algo(&mut player, &board);
-                },
-                Err(error) => message = error,
-            }
+            Some(MoveDir::Up) => "Up",
+            Some(MoveDir::Down) => "Down",
+            Some(MoveDir::Left) => "Left",
+            Some(MoveDir::Right) => "Right",
+            None => "",
         };
-        
+        easy.print(format!("Suggested ({}): {}", strategy.name(), move_str));
+
+
         let input = easy.get_input();
-        match input {
-            Some(Input::KeyLeft) => try_play(MoveDir::Left),
-            Some(Input::KeyRight) => try_play(MoveDir::Right),
-            Some(Input::KeyUp) => try_play(MoveDir::Up),
-            Some(Input::KeyDown) => try_play(MoveDir::Down),
-            other => message = format!("Unknown: {:?}", other),
+        let changed = match input {
+            Some(Input::KeyLeft) => try_play(&mut game, MoveDir::Left, &mut message),
+            Some(Input::KeyRight) => try_play(&mut game, MoveDir::Right, &mut message),
+            Some(Input::KeyUp) => try_play(&mut game, MoveDir::Up, &mut message),
+            Some(Input::KeyDown) => try_play(&mut game, MoveDir::Down, &mut message),
+            Some(Input::Character('u')) => game.undo(),
+            Some(Input::Character('r')) => game.redo(),
+            Some(Input::Character('s')) => {
+                message = save(&game);
+                false
+            },
+            other => {
+                message = format!("Unknown: {:?}", other);
+                false
+            },
+        };
+        if changed {
+            // Whatever was said about the last position is out of date now
+            message.clear();
+            suggested_move = strategy.choose(&mut hint_player, game.board());
         }
     }
 }
\ No newline at end of file
@@ -1,3 +1,12 @@
 pub mod gameplay;
+pub mod merge;
+pub mod threes;
+pub mod bitboard;
+pub mod history;
+pub mod record;
+pub mod symmetry;
+pub mod events;
+pub mod adversary;
 pub mod simulate;
 pub mod algorithm;
+pub mod ntuple;
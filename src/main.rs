@@ -3,26 +3,21 @@
 mod interactive;
 
 extern crate twentysixtyfour;
-use twentysixtyfour::{simulate, gameplay, algorithm};
+use twentysixtyfour::{simulate, gameplay, algorithm, adversary, history, record, ntuple, threes};
+use twentysixtyfour::algorithm::{Strategy, ScoreFunction, RolloutPolicy, RolloutObjective, MctsConfig};
+use twentysixtyfour::merge::MergeRule;
 
 extern crate clap;
 use clap::{App, Arg};
 
 extern crate flame;
 
-use serde_yaml;
-
 
 use std::fs::{write};
 use std::collections::BTreeMap;
 
 
 
-struct AlgoEntry {
-    name: String,
-    func: fn(&mut gameplay::GamePlayer, &gameplay::Board) -> gameplay::MoveDir,
-}
-
 fn main() {
     let matches = App::new("2064")
         .about("Implements 2064 game and tests play strategies")
@@ -36,51 +31,235 @@ fn main() {
             .long("flame")
             .help("Run flame profile")
         )
+        .arg(Arg::with_name("board")
+            .short("b")
+            .long("board")
+            .takes_value(true)
+            .help("Starting position for --interactive or --flame, e.g. \"128,2,2,8/256,8,16,8/256,8,.,./64,32,.,.\"")
+        )
+        .arg(Arg::with_name("train")
+            .long("train")
+            .takes_value(true)
+            .help("Train an n-tuple network by playing this many games against itself, continuing from --weights if the file exists, and save it there")
+        )
+        .arg(Arg::with_name("weights")
+            .long("weights")
+            .takes_value(true)
+            .help("N-tuple network weights file, written by --train. If given, the network's strategies are simulated too")
+        )
+        .arg(Arg::with_name("hints")
+            .long("hints")
+            .takes_value(true)
+            .default_value("lookaheadsorted5")
+            .help("Strategy suggesting moves in --interactive, by its name in the report, e.g. mcts200")
+        )
+        .arg(Arg::with_name("size")
+            .long("size")
+            .takes_value(true)
+            .default_value("4x4")
+            .help("Board size as rows x columns, e.g. 5x5 or 4x6")
+        )
+        .arg(Arg::with_name("seed")
+            .short("s")
+            .long("seed")
+            .takes_value(true)
+            .default_value("0")
+            .help("Base seed for simulated games")
+        )
+        .arg(Arg::with_name("threads")
+            .short("t")
+            .long("threads")
+            .takes_value(true)
+            .default_value("0")
+            .help("Number of threads for simulated games (0 for one per core)")
+        )
+        .arg(Arg::with_name("games")
+            .long("games")
+            .help("Include every simulated game in report.yml, not just the summary")
+        )
+        .arg(Arg::with_name("merge")
+            .long("merge")
+            .takes_value(true)
+            .default_value("standard")
+            .help("Rules for merging tiles: standard, fibonacci, powers-of-three, sum, or threes for the Threes! game. Simulations take a comma separated list, or all")
+        )
+        .arg(Arg::with_name("spawn")
+            .long("spawn")
+            .takes_value(true)
+            .help("Values of new tiles in simulated games, with their relative weights, e.g. 2:9,4:1 (default: the usual values for the merge rule)")
+        )
+        .arg(Arg::with_name("tiles-per-move")
+            .long("tiles-per-move")
+            .takes_value(true)
+            .help("Number of tiles spawned after each move in simulated games (default: the merge rule's usual number)")
+        )
+        .arg(Arg::with_name("start-tiles")
+            .long("start-tiles")
+            .takes_value(true)
+            .help("Number of tiles at the start of simulated games (default: the merge rule's usual number)")
+        )
+        .arg(Arg::with_name("fixed-start")
+            .long("fixed-start")
+            .help("Start simulated games from a single tile in the corner (a 2 for the standard rule) instead of random tiles")
+        )
+        .arg(Arg::with_name("adversary")
+            .long("adversary")
+            .takes_value(true)
+            .possible_values(&["worst-case"])
+            .help("Let an adversary place the tiles spawned in simulated games instead of spawning them at random. worst-case places each tile where it leaves the player's best next position ranked lowest")
+        )
+        .arg(Arg::with_name("blockers")
+            .long("blockers")
+            .takes_value(true)
+            .default_value("0")
+            .help("Number of blocked cells, which tiles can't pass or merge with, put on the board at the start of a game")
+        )
+        .arg(Arg::with_name("wildcards")
+            .long("wildcards")
+            .takes_value(true)
+            .default_value("0")
+            .help("Number of wildcard tiles, which merge with any tile, put on the board at the start of a game")
+        )
         .get_matches();
     
+    let merges = parse_merge_rules(matches.value_of("merge").unwrap());
+
+    let board: Option<gameplay::Board> = matches.value_of("board").map(|text| {
+        gameplay::Board::parse(text, merges[0]).unwrap_or_else(|e| {
+            eprintln!("Invalid board \"{}\": {}", text, e);
+            std::process::exit(1);
+        })
+    });
+
+    let (rows, cols) = parse_size(matches.value_of("size").unwrap());
+    let blockers: usize = matches.value_of("blockers").unwrap().parse().expect("Blockers must be an unsigned integer");
+    let wildcards: usize = matches.value_of("wildcards").unwrap().parse().expect("Wildcards must be an unsigned integer");
+
+    // The strategies simulated, any of which can give hints when playing
+    // interactively
+    let mut tests: Vec<Box<dyn Strategy>> = vec![
+        Box::new(algorithm::Random),
+        Box::new(algorithm::Random3Dir),
+        Box::new(algorithm::MaxFreeSpace),
+        Box::new(algorithm::MaxFreeSpace3Dir),
+        Box::new(algorithm::NaiveLookahead{moves: 1, score_fn: ScoreFunction::FreeSpace}),
+        Box::new(algorithm::NaiveLookahead{moves: 1, score_fn: ScoreFunction::FreeSpaceWithSortedness}),
+        Box::new(algorithm::NaiveLookahead{moves: 3, score_fn: ScoreFunction::FreeSpace}),
+        Box::new(algorithm::NaiveLookahead{moves: 3, score_fn: ScoreFunction::FreeSpaceWithSortedness}),
+        Box::new(algorithm::NaiveLookahead{moves: 5, score_fn: ScoreFunction::FreeSpace}),
+        Box::new(algorithm::NaiveLookahead{moves: 5, score_fn: ScoreFunction::FreeSpaceWithSortedness}),
+        Box::new(algorithm::Expectimax{depth: 1, score_fn: ScoreFunction::FreeSpaceWithSortedness}),
+        Box::new(algorithm::Expectimax{depth: 2, score_fn: ScoreFunction::FreeSpaceWithSortedness}),
+        Box::new(algorithm::MonteCarlo{rollouts: 10, policy: RolloutPolicy::Random, objective: RolloutObjective::Score, time_budget: None}),
+        Box::new(algorithm::MonteCarlo{rollouts: 10, policy: RolloutPolicy::MaxFreeSpace, objective: RolloutObjective::Score, time_budget: None}),
+        Box::new(algorithm::Mcts::new(MctsConfig::default())),
+    ];
+    if let (Some(path), false) = (matches.value_of("weights"), matches.is_present("train")) {
+        let network = ntuple::NTupleNetwork::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        // The network only knows boards of the size and merge rule it was
+        // trained for, so it can't play any others
+        for merge in merges.iter() {
+            let options = gameplay::GameOptions{rows, cols, ..gameplay::GameOptions::for_merge(*merge)};
+            network.check_options(&options).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            });
+        }
+        let network = std::sync::Arc::new(network);
+        tests.push(Box::new(ntuple::NTupleStrategy{network: network.clone(), depth: 0}));
+        tests.push(Box::new(ntuple::NTupleStrategy{network, depth: 1}));
+    }
+
     if matches.is_present("interactive") {
         println!("Running interactive");
-        let algo: fn(&mut gameplay::GamePlayer, &gameplay::Board) -> gameplay::MoveDir = |mut player, board| {algorithm::naive_lookahead(&mut player, board, 5, algorithm::ScoreFunction::FreeSpaceWithSortedness)};
-        interactive::run(algo);
+        let hints = matches.value_of("hints").unwrap();
+        let mut strategy = tests.iter().find(|strategy| strategy.name() == hints).map(|strategy| strategy.clone_box()).unwrap_or_else(|| {
+            let names: Vec<String> = tests.iter().map(|strategy| strategy.name()).collect();
+            eprintln!("Unknown strategy \"{}\", expected one of {}", hints, names.join(", "));
+            std::process::exit(1);
+        });
+        // Like the real game, let the player carry on after reaching the target
+        let options = gameplay::GameOptions{keep_playing: true, rows, cols, blockers, wildcards, ..gameplay::GameOptions::for_merge(merges[0])};
+        interactive::run(strategy.as_mut(), options, board);
+    } else if let Some(games) = matches.value_of("train") {
+        let games: usize = games.parse().expect("Training games must be an unsigned integer");
+        let path = matches.value_of("weights").unwrap_or("weights.json");
+        train(path, games, gameplay::GameOptions{rows, cols, blockers, wildcards, ..gameplay::GameOptions::for_merge(merges[0])});
     } else if matches.is_present("flame") {
-        let board = gameplay::Board{
-            values:  [128, 2,  2,  8,
-                      256, 8,  16, 8,
-                      256, 8,  0,  0,
-                      64,  32, 0,  0],
-            score: 0
-        };
+        let board = board.unwrap_or_else(|| "128,2,2,8/256,8,16,8/256,8,.,./64,32,.,.".parse().unwrap());
         //flame::start("a");
         let mut player = gameplay::GamePlayer::default();
+        let mut strategy = algorithm::NaiveLookahead{moves: 5, score_fn: ScoreFunction::FreeSpaceWithSortedness};
         loop
         {
             //let _guard = flame::start_guard("naive_lookahead3");
-            let _dir = algorithm::naive_lookahead(&mut player, &board, 5, algorithm::ScoreFunction::FreeSpaceWithSortedness);
+            let _dir = strategy.choose(&mut player, &board);
         }
         // flame::end("a");
         // flame::dump_html(&mut File::create("flame-graph.html").unwrap()).unwrap();
     } else {
         
         const NRUNS: i32 = 200;
+        let configs: Vec<simulate::SimulationConfig> = merges.iter().map(|merge| {
+            let mut config = simulate::SimulationConfig{
+                runs: NRUNS,
+                base_seed: matches.value_of("seed").unwrap().parse().expect("Seed must be an unsigned integer"),
+                threads: matches.value_of("threads").unwrap().parse().expect("Threads must be an unsigned integer"),
+                keep_games: matches.is_present("games"),
+                ..simulate::SimulationConfig::for_merge(*merge)
+            };
+            if let Some(text) = matches.value_of("spawn") {
+                config.spawn.values = parse_spawn_values(text);
+            }
+            // Left alone, these keep the spawns usual for the merge rule
+            if let Some(text) = matches.value_of("tiles-per-move") {
+                config.spawn.tiles_per_move = text.parse().expect("Tiles per move must be an unsigned integer");
+            }
+            if let Some(text) = matches.value_of("start-tiles") {
+                config.spawn.start_tiles = text.parse().expect("Start tiles must be an unsigned integer");
+            }
+            if !config.spawn.is_valid(*merge) {
+                eprintln!("Spawn values must be {} tiles with some weight, and at least one tile must spawn per move", merge.name());
+                std::process::exit(1);
+            }
+            config.game.rows = rows;
+            config.game.cols = cols;
+            if matches.value_of("adversary").is_some() {
+                config.adversary = Some(Box::new(adversary::WorstCase{score_fn: ScoreFunction::FreeSpaceWithSortedness}));
+            }
+            config.game.blockers = blockers;
+            config.game.wildcards = wildcards;
+            if matches.is_present("fixed-start") {
+                config.game.start = gameplay::StartPosition::Fixed;
+            }
+            config
+        }).collect();
         let mut report = BTreeMap::new();
 
-        let tests = vec![
-            AlgoEntry{name: "random".to_string(), func: algorithm::random}, 
-            AlgoEntry{name: "random_3dir".to_string(), func: algorithm::random_3dir},
-            AlgoEntry{name: "max_free_space".to_string(), func: algorithm::max_free_space},
-            AlgoEntry{name: "max_free_space_3dir".to_string(), func: algorithm::max_free_space_3dir},
-            AlgoEntry{name: "lookahead1".to_string(), func: |mut player, board| algorithm::naive_lookahead(&mut player, board, 1, algorithm::ScoreFunction::FreeSpace)},
-            AlgoEntry{name: "lookaheadsorted1".to_string(), func: |mut player, board| algorithm::naive_lookahead(&mut player, board, 1, algorithm::ScoreFunction::FreeSpaceWithSortedness)},
-            AlgoEntry{name: "lookahead3".to_string(), func: |mut player, board| algorithm::naive_lookahead(&mut player, board, 3, algorithm::ScoreFunction::FreeSpace)},
-            AlgoEntry{name: "lookaheadsorted3".to_string(), func: |mut player, board| algorithm::naive_lookahead(&mut player, board, 3, algorithm::ScoreFunction::FreeSpaceWithSortedness)},
-            AlgoEntry{name: "lookahead5".to_string(), func: |mut player, board| algorithm::naive_lookahead(&mut player, board, 5, algorithm::ScoreFunction::FreeSpace)},
-            AlgoEntry{name: "lookaheadsorted5".to_string(), func: |mut player, board| algorithm::naive_lookahead(&mut player, board, 5, algorithm::ScoreFunction::FreeSpaceWithSortedness)},
-        ];
-
-        for t in tests {
-            println!("Running {}...", t.name);
-            let results = simulate::bulk(t.func, NRUNS);
-            report.insert(t.name, results);
+
+        for config in configs.iter() {
+            // Results for the standard rule keep the plain strategy names
+            let merge = config.game.merge;
+            let mut suffix = if merge == MergeRule::Standard { String::new() } else { format!("_{}", merge.name()) };
+            if let Some(adversary) = &config.adversary {
+                suffix += &format!("_{}", adversary.name());
+            }
+            for strategy in tests.iter() {
+                let strategy_config = strategy.config();
+                if strategy_config.is_empty() {
+                    println!("Running {}{}...", strategy.name(), suffix);
+                } else {
+                    println!("Running {}{} ({})...", strategy.name(), suffix, strategy_config);
+                }
+                let results = simulate::bulk(strategy.as_ref(), config).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+                report.insert(format!("{}{}", strategy.name(), suffix), results);
+            }
         }
 
         println!("Done. Writing report");
@@ -89,3 +268,75 @@ fn main() {
         write("report.yml", s).unwrap();
     }
 }
+
+// Train an n-tuple network by self-play, reporting progress as it goes, and
+// save it to path
+fn train(path: &str, games: usize, options: gameplay::GameOptions) {
+    const REPORT_EVERY: usize = 1000;
+    let mut network = if std::path::Path::new(path).exists() {
+        ntuple::NTupleNetwork::load(path)
+    } else {
+        ntuple::NTupleNetwork::new(ntuple::NTupleConfig{rows: options.rows, cols: options.cols, merge: options.merge, ..ntuple::NTupleConfig::default()})
+    }.and_then(|network| {
+        // Weights already there must be for the games being trained on
+        network.check_options(&options)?;
+        Ok(network)
+    }).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+    let mut player = gameplay::GamePlayer::default().with_rules(gameplay::SpawnRules::for_merge(options.merge), options.merge).expect("usual spawn rules are valid");
+    let mut played = 0;
+    while played < games {
+        let batch = network.train(&mut player, &options, REPORT_EVERY.min(games - played)).expect("the network was checked against the options");
+        played += batch.len();
+        let mean = batch.iter().map(|game| game.score as f64).sum::<f64>() / batch.len() as f64;
+        let best = batch.iter().map(|game| game.largest).max().unwrap_or(0);
+        println!("{} games: mean score {:.0}, largest tile {}", played, mean, best);
+    }
+    network.save(path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    println!("Saved weights to {}", path);
+}
+
+// Parse spawn values given as value:weight pairs, e.g. "2:9,4:1"
+fn parse_spawn_values(text: &str) -> Vec<(i32, u32)> {
+    text.split(',').map(|pair| {
+        let parsed = pair.split_once(':').and_then(|(value, weight)| {
+            Some((value.trim().parse().ok()?, weight.trim().parse().ok()?))
+        });
+        parsed.unwrap_or_else(|| {
+            eprintln!("Invalid spawn value \"{}\", expected value:weight", pair);
+            std::process::exit(1);
+        })
+    }).collect()
+}
+
+// Parse merge rules given by name, e.g. "standard,fibonacci", or "all"
+fn parse_merge_rules(text: &str) -> Vec<MergeRule> {
+    if text == "all" {
+        return MergeRule::ALL.to_vec();
+    }
+    text.split(',').map(|name| {
+        MergeRule::from_name(name.trim()).unwrap_or_else(|| {
+            eprintln!("Unknown merge rule \"{}\", expected standard, fibonacci, powers-of-three, sum, threes or all", name);
+            std::process::exit(1);
+        })
+    }).collect()
+}
+
+// Parse a board size given as rows x columns, e.g. "4x6"
+fn parse_size(text: &str) -> (usize, usize) {
+    let parsed = text.split_once('x').and_then(|(rows, cols)| {
+        Some((rows.trim().parse().ok()?, cols.trim().parse().ok()?))
+    });
+    match parsed {
+        Some((rows, cols)) if rows >= 2 && cols >= 2 => (rows, cols),
+        _ => {
+            eprintln!("Invalid board size \"{}\", expected rows x columns of at least 2, e.g. 5x5", text);
+            std::process::exit(1);
+        },
+    }
+}
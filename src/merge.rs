@@ -0,0 +1,337 @@
+use super::gameplay::WILDCARD;
+
+use serde::{Serialize, Deserialize};
+
+// Which tiles combine when they are pushed together, and what they make. The
+// rule decides the values a tile can have, so a board carries its rule with
+// it and every move, validity check and score on that board follows it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum MergeRule {
+    // Two equal tiles make one of twice the value. Tiles are powers of two
+    // from 2 up.
+    #[default]
+    Standard,
+    // Two neighbouring Fibonacci numbers make the next one, e.g. 3 and 5 make
+    // 8, and two 1s make 2. Tiles are Fibonacci numbers from 1 up.
+    Fibonacci,
+    // Three equal tiles make one of three times the value. Tiles are powers
+    // of three from 3 up.
+    PowersOfThree,
+    // Two equal tiles make their sum, as in the standard game, but any
+    // positive value is a tile, so spawns needn't be powers of two.
+    SumEqual,
+    // A 1 and a 2 make 3, and two equal tiles of 3 or more make double. Tiles
+    // only move one cell per move under this rule; see the threes module.
+    Threes,
+}
+
+impl MergeRule {
+    pub const ALL: [MergeRule; 5] = [
+        MergeRule::Standard,
+        MergeRule::Fibonacci,
+        MergeRule::PowersOfThree,
+        MergeRule::SumEqual,
+        MergeRule::Threes,
+    ];
+
+    // Short name, used on the command line and in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            MergeRule::Standard => "standard",
+            MergeRule::Fibonacci => "fibonacci",
+            MergeRule::PowersOfThree => "powers-of-three",
+            MergeRule::SumEqual => "sum",
+            MergeRule::Threes => "threes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MergeRule> {
+        MergeRule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+
+    // True for values that can be a tile under this rule
+    pub fn is_tile(self, value: i32) -> bool {
+        match self {
+            MergeRule::Standard => value >= 2 && (value & (value - 1)) == 0,
+            MergeRule::Fibonacci => is_fibonacci(value),
+            MergeRule::PowersOfThree => {
+                let mut power = 3;
+                while power < value {
+                    power = match power.checked_mul(3) {
+                        Some(next) => next,
+                        None => return false,
+                    };
+                }
+                power == value
+            },
+            MergeRule::SumEqual => value >= 1,
+            MergeRule::Threes => value == 1 || value == 2
+                || (value >= 3 && value % 3 == 0 && MergeRule::Standard.is_tile(value / 3 * 2)),
+        }
+    }
+
+    // The tile a fixed start (see Board::init_fixed) puts in the corner
+    pub fn start_tile(self) -> i32 {
+        match self {
+            MergeRule::Fibonacci => 1,
+            MergeRule::PowersOfThree | MergeRule::Threes => 3,
+            MergeRule::Standard | MergeRule::SumEqual => 2,
+        }
+    }
+
+    // Number of tiles that combine in one merge
+    pub fn group_size(self) -> usize {
+        match self {
+            MergeRule::PowersOfThree => 3,
+            _ => 2,
+        }
+    }
+
+    // The tile made by merging tiles, or None if they don't merge. tiles has
+    // group_size() valid tiles in it. Wildcards stand in for copies of the
+    // other tiles, so they merge when all the other tiles are equal, making
+    // the tile one of those would make with its copies (see promote). Only
+    // wildcards don't merge, as there is nothing for them to copy.
+    pub fn merge(self, tiles: &[i32]) -> Option<i32> {
+        if tiles.contains(&WILDCARD) {
+            let mut real = tiles.iter().filter(|tile| **tile != WILDCARD);
+            let first = *real.next()?;
+            return if real.all(|tile| *tile == first) { self.promote(first) } else { None };
+        }
+        let first = tiles[0];
+        match self {
+            MergeRule::Fibonacci => {
+                let sum = first.checked_add(tiles[1])?;
+                Some(sum).filter(|sum| is_fibonacci(*sum))
+            },
+            MergeRule::Threes if first < 3 => Some(3).filter(|_| first + tiles[1] == 3),
+            _ if tiles.iter().all(|tile| *tile == first) => first.checked_mul(tiles.len() as i32),
+            _ => None,
+        }
+    }
+
+    // The tile made by merging value with copies of itself, or for the rules
+    // where equal tiles don't merge, with whichever tile it would merge with
+    // to make the next one up
+    pub fn promote(self, value: i32) -> Option<i32> {
+        match self {
+            MergeRule::Fibonacci => {
+                let (mut a, mut b) = (1i32, 2i32);
+                while a <= value {
+                    let next = a.checked_add(b);
+                    a = b;
+                    b = match next {
+                        Some(next) => next,
+                        // a is the last Fibonacci number an i32 holds
+                        None if a > value => break,
+                        None => return None,
+                    };
+                }
+                Some(a)
+            },
+            MergeRule::Threes if value < 3 => Some(3),
+            _ => value.checked_mul(self.group_size() as i32),
+        }
+    }
+
+    // Merge the tiles of one line the way a move does, for the rules where
+    // tiles slide as far as they can (all but Threes). tiles are the line's
+    // tiles (without gaps) in order from the edge being moved towards. Working
+    // back from that edge, each group of tiles which merge becomes one tile,
+    // and no tile takes part in more than one merge. Returns the tiles after
+    // the move in the same order, each with the number of tiles that went
+    // into it.
+    pub fn merge_tiles(self, tiles: &[i32]) -> Vec<(i32, usize)> {
+        let size = self.group_size();
+        let mut out = Vec::with_capacity(tiles.len());
+        let mut i = 0;
+        while i < tiles.len() {
+            match tiles.get(i..i + size).and_then(|group| self.merge(group)) {
+                Some(value) => {
+                    out.push((value, size));
+                    i += size;
+                },
+                None => {
+                    out.push((tiles[i], 1));
+                    i += 1;
+                },
+            }
+        }
+        out
+    }
+
+    // True if some group of neighbouring tiles in a line would merge
+    pub fn can_merge(self, tiles: &[i32]) -> bool {
+        tiles.windows(self.group_size()).any(|group| self.merge(group).is_some())
+    }
+
+    // The tile rank places up from the smallest, e.g. 2, 4, 8... for the
+    // standard rule and 1, 2, 3, 6, 12... for Threes, or None if it's too
+    // big for an i32. Every value from 1 up is a SumEqual tile, so there the
+    // ranks are bands of values starting at 1, 2, 4, 8 and so on.
+    pub fn tile(self, rank: usize) -> Option<i32> {
+        let mut tile: i32 = match self {
+            MergeRule::Standard => 2,
+            MergeRule::PowersOfThree => 3,
+            MergeRule::Fibonacci | MergeRule::SumEqual | MergeRule::Threes => 1,
+        };
+        for _ in 0..rank {
+            tile = match self {
+                MergeRule::Threes if tile < 3 => tile + 1,
+                MergeRule::SumEqual => tile.checked_mul(2)?,
+                _ => self.promote(tile)?,
+            };
+        }
+        Some(tile)
+    }
+
+    // The target a game is won by reaching, unless it sets its own: 2048 in
+    // the standard game, and for other rules the smallest of their tiles
+    // from 2048 up. Games are won once the largest tile reaches the target,
+    // so this wins at the same point as 2048 would, but names a tile the
+    // game can actually show.
+    pub fn default_target(self) -> i32 {
+        self.tile(self.tile_rank(2047) + 1).expect("tiles go well past 2048")
+    }
+
+    // The rank (see tile) of the largest tile no bigger than value, so each
+    // tile gets its own rank, in order, and anything between two tiles goes
+    // with the smaller. Values below the smallest tile get rank 0.
+    pub fn tile_rank(self, value: i32) -> usize {
+        let log2 = |v: i32| (31 - v.leading_zeros()) as usize;
+        match self {
+            MergeRule::Standard if value >= 2 => log2(value) - 1,
+            MergeRule::SumEqual if value >= 1 => log2(value),
+            MergeRule::Threes if value >= 3 => log2(value / 3) + 2,
+            MergeRule::Threes if value == 2 => 1,
+            MergeRule::PowersOfThree => {
+                let (mut rank, mut power) = (0, 9);
+                while power <= value {
+                    rank += 1;
+                    power = match power.checked_mul(3) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                }
+                rank
+            },
+            MergeRule::Fibonacci => {
+                let (mut rank, mut a, mut b) = (0, 2i32, 3i32);
+                while a <= value {
+                    rank += 1;
+                    let next = match a.checked_add(b) {
+                        Some(next) => next,
+                        None => return if b <= value { rank + 1 } else { rank },
+                    };
+                    a = b;
+                    b = next;
+                }
+                rank
+            },
+            _ => 0,
+        }
+    }
+}
+
+// Fibonacci numbers from 1 up. 1 appears twice in the sequence, which makes
+// no difference here.
+fn is_fibonacci(value: i32) -> bool {
+    let (mut a, mut b) = (1i32, 2i32);
+    while a < value {
+        let next = match a.checked_add(b) {
+            Some(next) => next,
+            None => return b == value,
+        };
+        a = b;
+        b = next;
+    }
+    a == value
+}
+
+#[cfg(test)]
+mod merge_tests {
+    use super::*;
+
+    fn merged(rule: MergeRule, tiles: &[i32]) -> Vec<i32> {
+        rule.merge_tiles(tiles).iter().map(|(value, _)| *value).collect()
+    }
+
+    #[test]
+    fn test_tiles() {
+        let tiles = |rule: MergeRule| -> Vec<i32> { (0..100).filter(|v| rule.is_tile(*v)).collect() };
+        assert_eq!(tiles(MergeRule::Standard), vec![2, 4, 8, 16, 32, 64]);
+        assert_eq!(tiles(MergeRule::Fibonacci), vec![1, 2, 3, 5, 8, 13, 21, 34, 55, 89]);
+        assert_eq!(tiles(MergeRule::PowersOfThree), vec![3, 9, 27, 81]);
+        assert_eq!(tiles(MergeRule::SumEqual).len(), 99);
+        assert_eq!(tiles(MergeRule::Threes), vec![1, 2, 3, 6, 12, 24, 48, 96]);
+        assert!(MergeRule::Fibonacci.is_tile(1_836_311_903));
+        assert!(!MergeRule::PowersOfThree.is_tile(i32::MAX));
+        for rule in MergeRule::ALL.iter() {
+            assert!(rule.is_tile(rule.start_tile()));
+            assert_eq!(MergeRule::from_name(rule.name()), Some(*rule));
+        }
+    }
+
+    #[test]
+    fn tile_ranks() {
+        let first = |rule: MergeRule| -> Vec<i32> { (0..6).map(|rank| rule.tile(rank).unwrap()).collect() };
+        assert_eq!(first(MergeRule::Standard), vec![2, 4, 8, 16, 32, 64]);
+        assert_eq!(first(MergeRule::Fibonacci), vec![1, 2, 3, 5, 8, 13]);
+        assert_eq!(first(MergeRule::PowersOfThree), vec![3, 9, 27, 81, 243, 729]);
+        assert_eq!(first(MergeRule::SumEqual), vec![1, 2, 4, 8, 16, 32]);
+        assert_eq!(first(MergeRule::Threes), vec![1, 2, 3, 6, 12, 24]);
+        let targets: Vec<i32> = MergeRule::ALL.iter().map(|rule| rule.default_target()).collect();
+        assert_eq!(targets, vec![2048, 2584, 2187, 2048, 3072]);
+        for rule in MergeRule::ALL.iter() {
+            let mut rank = 0;
+            while let Some(tile) = rule.tile(rank) {
+                assert_eq!(rule.tile_rank(tile), rank, "{:?} {}", rule, tile);
+                assert_eq!(rule.tile_rank(tile - 1), rank.saturating_sub(1), "{:?} {}", rule, tile - 1);
+                rank += 1;
+            }
+            assert!(rank > 18);
+            assert_eq!(rule.tile_rank(i32::MAX), rank - 1);
+            assert!(rule.is_tile(rule.default_target()));
+            assert_eq!(rule.tile_rank(0), 0);
+            assert_eq!(rule.tile_rank(-1), 0);
+        }
+    }
+
+    #[test]
+    fn test_merge_tiles() {
+        assert_eq!(merged(MergeRule::Standard, &[2, 2, 2, 2]), vec![4, 4]);
+        assert_eq!(merged(MergeRule::Standard, &[4, 2, 2]), vec![4, 4]);
+        assert_eq!(merged(MergeRule::Fibonacci, &[1, 1, 2]), vec![2, 2]);
+        assert_eq!(merged(MergeRule::Fibonacci, &[2, 3, 5, 5]), vec![5, 5, 5]);
+        assert_eq!(merged(MergeRule::Fibonacci, &[1, 3, 2, 2]), vec![1, 5, 2]);
+        assert_eq!(merged(MergeRule::PowersOfThree, &[3, 3, 9, 9, 9]), vec![3, 3, 27]);
+        assert_eq!(merged(MergeRule::PowersOfThree, &[3, 3, 3, 3]), vec![9, 3]);
+        assert_eq!(merged(MergeRule::SumEqual, &[3, 3, 5, 6, 6]), vec![6, 5, 12]);
+        assert_eq!(MergeRule::PowersOfThree.merge_tiles(&[9, 9, 9, 3]), vec![(27, 3), (3, 1)]);
+
+        assert!(MergeRule::Fibonacci.can_merge(&[8, 13]));
+        assert!(!MergeRule::Fibonacci.can_merge(&[2, 2, 8]));
+        assert!(!MergeRule::PowersOfThree.can_merge(&[3, 3]));
+        assert_eq!(MergeRule::Threes.merge(&[2, 1]), Some(3));
+        assert_eq!(MergeRule::Threes.merge(&[3, 3]), Some(6));
+        assert_eq!(MergeRule::Threes.merge(&[1, 1]), None);
+        assert_eq!(MergeRule::Threes.merge(&[2, 2]), None);
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert_eq!(MergeRule::Standard.merge(&[WILDCARD, 8]), Some(16));
+        assert_eq!(MergeRule::Standard.merge(&[WILDCARD, WILDCARD]), None);
+        assert_eq!(MergeRule::Fibonacci.merge(&[5, WILDCARD]), Some(8));
+        assert_eq!(MergeRule::Fibonacci.merge(&[1, WILDCARD]), Some(2));
+        assert_eq!(MergeRule::PowersOfThree.merge(&[9, WILDCARD, 9]), Some(27));
+        assert_eq!(MergeRule::PowersOfThree.merge(&[9, WILDCARD, 3]), None);
+        assert_eq!(MergeRule::Threes.merge(&[WILDCARD, 1]), Some(3));
+        assert_eq!(MergeRule::Threes.merge(&[WILDCARD, 6]), Some(12));
+        assert_eq!(merged(MergeRule::Standard, &[WILDCARD, WILDCARD, 2]), vec![WILDCARD, 4]);
+        assert_eq!(merged(MergeRule::Standard, &[4, WILDCARD, 2]), vec![8, 2]);
+        for rule in MergeRule::ALL.iter() {
+            assert!(rule.is_tile(rule.promote(rule.start_tile()).unwrap()));
+        }
+    }
+}
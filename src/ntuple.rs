@@ -0,0 +1,493 @@
+use super::gameplay::*;
+use super::merge::MergeRule;
+use super::symmetry::Transform;
+use super::algorithm::{Evaluator, Strategy, expectimax_move};
+
+use serde::{Serialize, Deserialize};
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+// An n-tuple network values a position as the sum of weights looked up by
+// the tiles in a few fixed groups of cells (the tuples). Each tuple has its
+// own table of weights, indexed by the tiles in its cells. With symmetric
+// weight sharing every tuple is also looked up in each symmetry of the board
+// (see symmetry::Transform), all sharing the one table, so positions which are
+// the same up to a rotation or reflection get the same value.
+//
+// The weights are learned with TD(0) on afterstates: the board after a move
+// slides but before its new tiles appear. Playing greedily by the current
+// weights, each afterstate's value is moved towards the score of the next
+// move plus the value of the afterstate after it (or 0 at the end of the
+// game), so that in the end it predicts the score still to come.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NTupleConfig {
+    // The size of board the network is for
+    pub rows: usize,
+    pub cols: usize,
+    // The merge rule the network is for, whose tile order the weight
+    // tables follow (see cell_index)
+    #[serde(default)]
+    pub merge: MergeRule,
+    // Each tuple's cells, as (row, column)
+    pub tuples: Vec<Vec<(usize, usize)>>,
+    // Number of different cell values told apart (see cell_index). Each
+    // tuple has values^(its length) weights.
+    pub values: usize,
+    // Look up each tuple in every symmetry of the board, sharing weights
+    pub symmetric: bool,
+    // Step size for TD updates, applied to every weight looked up
+    pub learning_rate: f32,
+}
+
+impl Default for NTupleConfig {
+    // A small network for the standard board: the outer and inner rows, and
+    // three squares. With symmetric lookups these cover every row, column
+    // and square of the board.
+    fn default() -> NTupleConfig {
+        NTupleConfig{
+            rows: DEFAULT_SIZE,
+            cols: DEFAULT_SIZE,
+            merge: MergeRule::Standard,
+            tuples: vec![
+                vec![(0, 0), (0, 1), (0, 2), (0, 3)],
+                vec![(1, 0), (1, 1), (1, 2), (1, 3)],
+                vec![(0, 0), (0, 1), (1, 0), (1, 1)],
+                vec![(0, 1), (0, 2), (1, 1), (1, 2)],
+                vec![(1, 1), (1, 2), (2, 1), (2, 2)],
+            ],
+            values: 16,
+            symmetric: true,
+            learning_rate: 0.0025,
+        }
+    }
+}
+
+// Most weights one tuple may have
+const MAX_TUPLE_WEIGHTS: usize = 1 << 28;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NTupleError {
+    // Fewer than two cell values can't tell an empty cell from a tile
+    TooFewValues { values: usize },
+    EmptyTuple { tuple: usize },
+    CellOutOfRange { tuple: usize, row: usize, col: usize },
+    // values^(tuple length) is more than MAX_TUPLE_WEIGHTS
+    TooManyWeights { tuple: usize },
+    // A weights file has the wrong number of tables for its config
+    TableCount { expected: usize, actual: usize },
+    // A weights file has the wrong number of weights for its config
+    WeightCount { tuple: usize, expected: usize, actual: usize },
+    // The network is for another board size or merge rule than the game's
+    WrongGame { rows: usize, cols: usize, merge: MergeRule },
+    Io(String),
+    Format(String),
+}
+
+impl fmt::Display for NTupleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NTupleError::TooFewValues { values } => write!(f, "Networks need at least 2 cell values, not {}", values),
+            NTupleError::EmptyTuple { tuple } => write!(f, "Tuple {} has no cells", tuple),
+            NTupleError::CellOutOfRange { tuple, row, col } => write!(f,
+                "Tuple {} has cell ({}, {}), which is off the board", tuple, row, col),
+            NTupleError::TooManyWeights { tuple } => write!(f, "Tuple {} needs too many weights", tuple),
+            NTupleError::TableCount { expected, actual } => write!(f,
+                "There are {} weight tables, but should be one per tuple ({})", actual, expected),
+            NTupleError::WeightCount { tuple, expected, actual } => write!(f,
+                "Tuple {} has {} weights, but should have {}", tuple, actual, expected),
+            NTupleError::WrongGame { rows, cols, merge } => write!(f,
+                "The network is not for {}x{} boards with the {} merge rule", rows, cols, merge.name()),
+            NTupleError::Io(e) => write!(f, "Could not access weights file: {}", e),
+            NTupleError::Format(e) => write!(f, "Invalid weights file: {}", e),
+        }
+    }
+}
+
+impl Error for NTupleError {}
+
+// The number of weights for a tuple with this many cells
+fn table_size(tuple: usize, cells: usize, values: usize) -> Result<usize, NTupleError> {
+    values.checked_pow(cells as u32)
+        .filter(|size| *size <= MAX_TUPLE_WEIGHTS)
+        .ok_or(NTupleError::TooManyWeights{tuple})
+}
+
+// The weight table index for one cell: 0 for empty, 1 for a blocker or
+// wildcard, and otherwise 2 for the smallest tile of the merge rule, 3 for
+// the next and so on (see MergeRule::tile_rank), with every tile too big for
+// the table sharing its last value
+fn cell_index(value: i32, merge: MergeRule, values: usize) -> usize {
+    match value {
+        0 => 0,
+        v if v < 0 => 1,
+        v => (merge.tile_rank(v) + 2).min(values - 1),
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "SavedNetwork")]
+pub struct NTupleNetwork {
+    config: NTupleConfig,
+    // A table per tuple
+    weights: Vec<Vec<f32>>,
+    // Every lookup made to value a board, as (tuple, board cell indices),
+    // worked out from the config
+    #[serde(skip)]
+    features: Vec<(usize, Vec<usize>)>,
+}
+
+// A network as it's read back, before its weights are checked against the
+// config and its lookups are worked out
+#[derive(Deserialize)]
+struct SavedNetwork {
+    config: NTupleConfig,
+    weights: Vec<Vec<f32>>,
+}
+
+impl TryFrom<SavedNetwork> for NTupleNetwork {
+    type Error = NTupleError;
+
+    fn try_from(saved: SavedNetwork) -> Result<NTupleNetwork, NTupleError> {
+        NTupleNetwork::with_weights(saved.config, saved.weights)
+    }
+}
+
+// How one training game went
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TrainingGame {
+    pub score: i32,
+    pub moves: i32,
+    pub largest: i32,
+}
+
+impl NTupleNetwork {
+    // A network with every weight 0
+    pub fn new(config: NTupleConfig) -> Result<NTupleNetwork, NTupleError> {
+        let mut weights = Vec::with_capacity(config.tuples.len());
+        for (tuple, cells) in config.tuples.iter().enumerate() {
+            weights.push(vec![0.0; table_size(tuple, cells.len(), config.values)?]);
+        }
+        NTupleNetwork::with_weights(config, weights)
+    }
+
+    fn with_weights(config: NTupleConfig, weights: Vec<Vec<f32>>) -> Result<NTupleNetwork, NTupleError> {
+        if config.values < 2 {
+            return Err(NTupleError::TooFewValues{values: config.values});
+        }
+        if weights.len() != config.tuples.len() {
+            return Err(NTupleError::TableCount{expected: config.tuples.len(), actual: weights.len()});
+        }
+        let (rows, cols) = (config.rows, config.cols);
+        // Boards which aren't square only have the symmetries that keep
+        // their shape
+        let transforms: Vec<Transform> = if config.symmetric {
+            Transform::ALL.iter().copied().filter(|t| rows == cols || !t.swaps_dims()).collect()
+        } else {
+            vec![Transform::Identity]
+        };
+        let mut features = Vec::new();
+        for (tuple, cells) in config.tuples.iter().enumerate() {
+            if cells.is_empty() {
+                return Err(NTupleError::EmptyTuple{tuple});
+            }
+            if let Some((row, col)) = cells.iter().find(|(row, col)| *row >= rows || *col >= cols) {
+                return Err(NTupleError::CellOutOfRange{tuple, row: *row, col: *col});
+            }
+            let expected = table_size(tuple, cells.len(), config.values)?;
+            if weights[tuple].len() != expected {
+                return Err(NTupleError::WeightCount{tuple, expected, actual: weights[tuple].len()});
+            }
+            for t in transforms.iter() {
+                let mapped = cells.iter()
+                    .map(|(row, col)| {
+                        let (r, c) = t.map_cell(*row, *col, rows, cols);
+                        r * cols + c
+                    })
+                    .collect();
+                features.push((tuple, mapped));
+            }
+        }
+        Ok(NTupleNetwork{config, weights, features})
+    }
+
+    pub fn config(&self) -> &NTupleConfig {
+        &self.config
+    }
+
+    // Whether the board is the size and merge rule the network is for. The
+    // weights mean nothing for any other board.
+    pub fn fits(&self, board: &Board) -> bool {
+        (board.rows(), board.cols(), board.merge) == (self.config.rows, self.config.cols, self.config.merge)
+    }
+
+    // Check that games with these options are ones the network is for
+    pub fn check_options(&self, options: &GameOptions) -> Result<(), NTupleError> {
+        if (options.rows, options.cols, options.merge) != (self.config.rows, self.config.cols, self.config.merge) {
+            return Err(NTupleError::WrongGame{rows: options.rows, cols: options.cols, merge: options.merge});
+        }
+        Ok(())
+    }
+
+    // The cell_index of every cell of a board, or None if the network isn't
+    // for it. Each cell is in several lookups, so this is worked out once per
+    // board.
+    fn cell_indices(&self, board: &Board) -> Option<Vec<usize>> {
+        if !self.fits(board) {
+            return None;
+        }
+        Some(board.values().iter().map(|value| cell_index(*value, self.config.merge, self.config.values)).collect())
+    }
+
+    fn index(&self, indices: &[usize], cells: &[usize]) -> usize {
+        cells.iter().fold(0, |index, cell| index * self.config.values + indices[*cell])
+    }
+
+    // The value of a board, or 0 for a board the network isn't for (see fits)
+    pub fn evaluate(&self, board: &Board) -> f32 {
+        let indices = match self.cell_indices(board) {
+            Some(indices) => indices,
+            None => return 0.0,
+        };
+        self.features.iter()
+            .map(|(tuple, cells)| self.weights[*tuple][self.index(&indices, cells)])
+            .sum()
+    }
+
+    // Move the value of board towards target
+    fn learn(&mut self, board: &Board, target: f32) {
+        let step = self.config.learning_rate * (target - self.evaluate(board));
+        let indices = match self.cell_indices(board) {
+            Some(indices) => indices,
+            None => return,
+        };
+        for f in 0..self.features.len() {
+            let index = self.index(&indices, &self.features[f].1);
+            self.weights[self.features[f].0][index] += step;
+        }
+    }
+
+    // The move whose score plus afterstate value is highest, with its
+    // afterstate and score, or None if there are no moves or the network
+    // isn't for the board
+    pub fn best_move(&self, board: &Board) -> Option<(MoveDir, Board, i32)> {
+        if !self.fits(board) {
+            return None;
+        }
+        let mut best = None;
+        let mut best_value = f32::MIN;
+        for dir in &[MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right] {
+            if let Ok((after, add_score)) = board.slide(*dir) {
+                let value = add_score as f32 + self.evaluate(&after);
+                if value > best_value {
+                    best_value = value;
+                    best = Some((*dir, after, add_score));
+                }
+            }
+        }
+        best
+    }
+
+    // Play one game against the player's spawns, choosing moves greedily by
+    // the current weights and learning from each move as it goes. Fails if
+    // the network isn't for games with these options.
+    pub fn train_game(&mut self, player: &mut GamePlayer, options: &GameOptions) -> Result<TrainingGame, NTupleError> {
+        self.check_options(options)?;
+        let mut board = player.new_game_board(options);
+        let mut moves = 0;
+        let mut last_after: Option<Board> = None;
+        while let Some((dir, after, add_score)) = self.best_move(&board) {
+            if let Some(last) = last_after.take() {
+                let target = add_score as f32 + self.evaluate(&after);
+                self.learn(&last, target);
+            }
+            // This makes the same slide, then spawns the new tiles
+            board = player.play(&board, dir).unwrap();
+            last_after = Some(after);
+            moves += 1;
+        }
+        // Nothing more is scored after the last move
+        if let Some(last) = last_after {
+            self.learn(&last, 0.0);
+        }
+        Ok(TrainingGame{score: board.score, moves, largest: board.largest()})
+    }
+
+    // Train on a number of games, returning how each went
+    pub fn train(&mut self, player: &mut GamePlayer, options: &GameOptions, games: usize) -> Result<Vec<TrainingGame>, NTupleError> {
+        (0..games).map(|_| self.train_game(player, options)).collect()
+    }
+
+    pub fn to_json(&self) -> Result<String, NTupleError> {
+        serde_json::to_string(self).map_err(|e| NTupleError::Format(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<NTupleNetwork, NTupleError> {
+        // Read as a SavedNetwork, so that a config the weights don't fit is
+        // reported as such rather than as a format error
+        let saved: SavedNetwork = serde_json::from_str(s).map_err(|e| NTupleError::Format(e.to_string()))?;
+        NTupleNetwork::try_from(saved)
+    }
+
+    // Write the config and weights to a file, which load reads back
+    pub fn save(&self, path: &str) -> Result<(), NTupleError> {
+        std::fs::write(path, self.to_json()?).map_err(|e| NTupleError::Io(e.to_string()))
+    }
+
+    pub fn load(path: &str) -> Result<NTupleNetwork, NTupleError> {
+        let s = std::fs::read_to_string(path).map_err(|e| NTupleError::Io(e.to_string()))?;
+        NTupleNetwork::from_json(&s)
+    }
+}
+
+// Searches value afterstates by the score so far plus the score the network
+// expects is still to come
+impl Evaluator for NTupleNetwork {
+    fn value(&self, board: &Board) -> f32 {
+        board.score as f32 + self.evaluate(board)
+    }
+
+    fn values_afterstates(&self) -> bool {
+        true
+    }
+
+    // As in training, no more is scored once the game is over
+    fn game_over_value(&self, board: &Board) -> f32 {
+        board.score as f32
+    }
+}
+
+// Plays by an n-tuple network: greedily, taking the move with the best score
+// plus afterstate value, or with the same expectimax search as Expectimax
+// looking depth moves past this one, which values the afterstates at its
+// leaves by the network. The network is shared between clones of the
+// strategy rather than copied.
+#[derive(Clone)]
+pub struct NTupleStrategy {
+    pub network: Arc<NTupleNetwork>,
+    pub depth: i32,
+}
+
+impl Strategy for NTupleStrategy {
+    fn name(&self) -> String {
+        if self.depth <= 0 {
+            "ntuple".to_string()
+        } else {
+            format!("ntuple_expectimax{}", self.depth)
+        }
+    }
+
+    fn config(&self) -> String {
+        format!("depth: {}, tuples: {}, symmetric: {}", self.depth, self.network.config.tuples.len(), self.network.config.symmetric)
+    }
+
+    fn choose(&mut self, player: &mut GamePlayer, board: &Board) -> Option<MoveDir> {
+        // The network can't value any other board, so has no move to suggest
+        if !self.network.fits(board) {
+            return None;
+        }
+        if self.depth <= 0 {
+            return self.network.best_move(board).map(|(dir, _, _)| dir);
+        }
+        expectimax_move(&player.rules, board, self.depth + 1, self.network.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod ntuple_tests {
+    use super::*;
+    use crate::simulate::{bulk, SimulationConfig};
+
+    #[test]
+    fn test_features() {
+        let config = NTupleConfig{tuples: vec![vec![(0, 0), (0, 1)]], values: 4, ..NTupleConfig::default()};
+        let mut network = NTupleNetwork::new(config.clone()).unwrap();
+        assert_eq!(network.features.len(), 8);
+        assert_eq!(network.weights[0].len(), 16);
+        // Each symmetry of a board looks up the same weights
+        let b: Board = "2,4,.,./.,.,.,./.,.,.,./.,.,.,8".parse().unwrap();
+        network.learn(&b, 100.0);
+        for t in Transform::ALL.iter() {
+            assert_eq!(network.evaluate(&b.transform(*t)), network.evaluate(&b));
+        }
+        assert!(network.evaluate(&b) > 0.0);
+        let one_way = NTupleNetwork::new(NTupleConfig{symmetric: false, ..config.clone()}).unwrap();
+        assert_eq!(one_way.features.len(), 1);
+
+        let off_board = NTupleConfig{tuples: vec![vec![(0, 4)]], ..config.clone()};
+        assert_eq!(NTupleNetwork::new(off_board).err(), Some(NTupleError::CellOutOfRange{tuple: 0, row: 0, col: 4}));
+        let huge = NTupleConfig{tuples: vec![vec![(0, 0); 10]], values: 16, ..config.clone()};
+        assert_eq!(NTupleNetwork::new(huge).err(), Some(NTupleError::TooManyWeights{tuple: 0}));
+        let one_value = NTupleConfig{values: 1, ..config.clone()};
+        assert_eq!(NTupleNetwork::new(one_value).err(), Some(NTupleError::TooFewValues{values: 1}));
+
+        // Weights read back must fit the config, however it was changed
+        assert_eq!(NTupleNetwork::with_weights(config.clone(), vec![]).err(), Some(NTupleError::TableCount{expected: 1, actual: 0}));
+        let overflowing = NTupleConfig{tuples: vec![vec![(0, 0); 40]], ..config};
+        assert_eq!(NTupleNetwork::with_weights(overflowing, vec![vec![]]).err(), Some(NTupleError::TooManyWeights{tuple: 0}));
+    }
+
+    #[test]
+    fn cell_indices() {
+        assert_eq!(cell_index(0, MergeRule::Standard, 16), 0);
+        assert_eq!(cell_index(BLOCKER, MergeRule::Standard, 16), 1);
+        assert_eq!(cell_index(WILDCARD, MergeRule::Threes, 16), 1);
+        // Each tile in order gets its own index, until the table runs out
+        let standard: Vec<usize> = [2, 4, 8, 2048, 16384, 32768, 65536].iter().map(|v| cell_index(*v, MergeRule::Standard, 16)).collect();
+        assert_eq!(standard, vec![2, 3, 4, 12, 15, 15, 15]);
+        let threes: Vec<usize> = [1, 2, 3, 6, 12, 24].iter().map(|v| cell_index(*v, MergeRule::Threes, 16)).collect();
+        assert_eq!(threes, vec![2, 3, 4, 5, 6, 7]);
+        let fibonacci: Vec<usize> = [1, 2, 3, 5, 8].iter().map(|v| cell_index(*v, MergeRule::Fibonacci, 16)).collect();
+        assert_eq!(fibonacci, vec![2, 3, 4, 5, 6]);
+        assert_eq!(cell_index(27, MergeRule::PowersOfThree, 4), 3);
+    }
+
+    #[test]
+    fn training_and_weights() {
+        let mut network = NTupleNetwork::new(NTupleConfig::default()).unwrap();
+        let mut player = GamePlayer::from_seed(1);
+        let games = network.train(&mut player, &GameOptions::default(), 200).unwrap();
+        assert_eq!(games.len(), 200);
+        let early: i32 = games[..50].iter().map(|g| g.score).sum();
+        let late: i32 = games[150..].iter().map(|g| g.score).sum();
+        assert!(late > early, "{} vs {}", late, early);
+
+        // Weights survive a round trip
+        let read = NTupleNetwork::from_json(&network.to_json().unwrap()).unwrap();
+        let b = player.new_board(StartPosition::Random);
+        assert_eq!(read.evaluate(&b), network.evaluate(&b));
+        assert!(NTupleNetwork::from_json("{}").is_err());
+        // Deserializing directly checks the weights and sets up the lookups too
+        let direct: NTupleNetwork = serde_json::from_str(&network.to_json().unwrap()).unwrap();
+        assert_eq!(direct.evaluate(&b), network.evaluate(&b));
+        let saved = r#"{"config": {"rows": 4, "cols": 4, "tuples": [[[0, 0]]], "values": 4, "symmetric": false, "learning_rate": 0.1}, "weights": [[]]}"#;
+        assert!(serde_json::from_str::<NTupleNetwork>(saved).is_err());
+
+        // Games of another size or merge rule aren't played or trained on
+        let wider = GameOptions{cols: 5, ..GameOptions::default()};
+        assert_eq!(network.clone().train_game(&mut player, &wider).err(), Some(NTupleError::WrongGame{rows: 4, cols: 5, merge: MergeRule::Standard}));
+        assert!(network.check_options(&GameOptions::for_merge(MergeRule::Fibonacci)).is_err());
+        let wide_board = Board::with_merge(4, 5, MergeRule::Standard);
+        assert!(NTupleStrategy{network: Arc::new(network.clone()), depth: 0}.choose(&mut player, &wide_board).is_none());
+
+        // The trained network beats random play, greedily and with a search
+        let network = Arc::new(network);
+        let config = SimulationConfig{runs: 4, threads: 2, ..SimulationConfig::default()};
+        let random = bulk(&crate::algorithm::Random, &config).unwrap().avg_score;
+        let greedy = NTupleStrategy{network: network.clone(), depth: 0};
+        assert!(bulk(&greedy, &config).unwrap().avg_score > random);
+        let searching = NTupleStrategy{network, depth: 1};
+        assert_eq!(searching.name(), "ntuple_expectimax1");
+        // The search knows Threes tiles only enter behind a move, given a
+        // network for Threes
+        let mut threes_player = GamePlayer::from_seed(2).with_rules(SpawnRules::for_merge(MergeRule::Threes), MergeRule::Threes).unwrap();
+        let threes = Board::parse("1,2,.,./.,3,.,./.,.,.,./.,.,.,.", MergeRule::Threes).unwrap();
+        assert!(searching.clone().choose(&mut threes_player, &threes).is_none());
+        let threes_network = NTupleNetwork::new(NTupleConfig{merge: MergeRule::Threes, ..NTupleConfig::default()}).unwrap();
+        assert!(NTupleStrategy{network: Arc::new(threes_network), depth: 1}.choose(&mut threes_player, &threes).is_some());
+        assert!(bulk(&searching, &SimulationConfig{runs: 2, ..config}).unwrap().avg_score > random);
+    }
+}
@@ -0,0 +1,254 @@
+use super::gameplay::*;
+use super::history::HistoryGame;
+
+use serde::{Serialize, Deserialize};
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedMove {
+    pub dir: MoveDir,
+    pub spawned: Vec<Spawn>,
+    // The board and score after the move, including the spawned tiles
+    pub values: Vec<i32>,
+    pub score: i32,
+}
+
+// Everything needed to save a game and play it back again
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub options: GameOptions,
+    // Records from before spawn rules were configurable used the defaults
+    #[serde(default)]
+    pub spawn: SpawnRules,
+    // Seed of the player which drew the starting tiles and every spawn, if
+    // the game came entirely from one seeded player. Games where moves were
+    // undone, or whose player was seeded from entropy, have no usable seed.
+    pub seed: Option<u64>,
+    // Cells of the starting board, which is options.rows x options.cols
+    pub initial: Vec<i32>,
+    pub moves: Vec<RecordedMove>,
+    pub final_score: i32,
+}
+
+impl GameRecord {
+    pub fn new(options: GameOptions, spawn: SpawnRules, seed: Option<u64>, initial: &Board) -> GameRecord {
+        GameRecord{options, spawn, seed, initial: initial.values().to_vec(), moves: Vec::new(), final_score: initial.score}
+    }
+
+    // Add a move, given its result and the board after it
+    pub fn push(&mut self, dir: MoveDir, result: &MoveResult, board: &Board) {
+        self.moves.push(RecordedMove{dir, spawned: result.spawned.clone(), values: board.values().to_vec(), score: board.score});
+        self.final_score = board.score;
+    }
+
+    // Record the moves leading to the history's current position
+    pub fn from_history(history: &HistoryGame, seed: Option<u64>) -> GameRecord {
+        let entries = &history.entries()[..=history.position()];
+        let game = history.game();
+        let mut record = GameRecord::new(*game.options(), game.rules().clone(), seed, &entries[0].board);
+        for entry in &entries[1..] {
+            // Only the first entry lacks a move
+            record.moves.push(RecordedMove{
+                dir: entry.dir.unwrap(),
+                spawned: entry.spawned.clone(),
+                values: entry.board.values().to_vec(),
+                score: entry.board.score,
+            });
+            record.final_score = entry.board.score;
+        }
+        record
+    }
+
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+
+    pub fn from_yaml(s: &str) -> Result<GameRecord, serde_yaml::Error> {
+        serde_yaml::from_str(s)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(s: &str) -> Result<GameRecord, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    // The starting board doesn't fit the board size, or the seed does not
+    // produce it
+    InitialBoard,
+    // The record has a seed, but spawn rules no player can use
+    SpawnRules(GameError),
+    // A recorded move could not be played
+    Move { index: usize, error: GameError },
+    // Replaying a move spawned different tiles from the record
+    Spawn { index: usize, expected: Vec<Spawn>, actual: Vec<Spawn> },
+    // Replaying a move gave a different board or score from the record
+    Board { index: usize },
+    FinalScore { expected: i32, actual: i32 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::InitialBoard => write!(f, "Seed does not reproduce the initial board"),
+            ReplayError::SpawnRules(error) => write!(f, "Can't spawn tiles by the recorded rules: {}", error),
+            ReplayError::Move { index, error } => write!(f, "Move {} failed: {}", index, error),
+            ReplayError::Spawn { index, expected, actual } => write!(f,
+                "Move {} spawned {}, but the record has {}", index, describe(actual), describe(expected)),
+            ReplayError::Board { index } => write!(f, "Board after move {} does not match the record", index),
+            ReplayError::FinalScore { expected, actual } => write!(f,
+                "Final score is {}, but the record has {}", actual, expected),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+// e.g. "a 2 in cell 3 and a 4 in cell 7"
+fn describe(spawned: &[Spawn]) -> String {
+    if spawned.is_empty() {
+        return "nothing".to_string();
+    }
+    let tiles: Vec<String> = spawned.iter().map(|s| format!("a {} in cell {}", s.value, s.cell)).collect();
+    tiles.join(" and ")
+}
+
+// Play a record back from its initial board, checking that every move gives
+// the recorded board. If the record has a seed, the moves are made with
+// play_inplace by a player with that seed, which must spawn the same tiles as
+// the record. Otherwise each move's recorded tiles are placed after sliding.
+// Returns the final board.
+pub fn replay(record: &GameRecord) -> Result<Board, ReplayError> {
+    let mut player = record.seed.map(|seed| GamePlayer::from_seed(seed).with_rules(record.spawn.clone(), record.options.merge))
+        .transpose()
+        .map_err(ReplayError::SpawnRules)?;
+    let (rows, cols) = (record.options.rows, record.options.cols);
+    if record.initial.len() != rows * cols {
+        return Err(ReplayError::InitialBoard);
+    }
+    let mut board = Board::with_values(rows, cols, record.initial.clone(), record.options.merge);
+    if let Some(player) = player.as_mut() {
+        if player.new_game_board(&record.options).values() != &record.initial[..] {
+            return Err(ReplayError::InitialBoard);
+        }
+    }
+
+    for (index, m) in record.moves.iter().enumerate() {
+        let move_error = |error| ReplayError::Move{index, error};
+        match player.as_mut() {
+            Some(player) => {
+                let result = player.play_inplace(&mut board, m.dir).map_err(move_error)?;
+                if result.spawned != m.spawned {
+                    return Err(ReplayError::Spawn{index, expected: m.spawned.clone(), actual: result.spawned});
+                }
+            },
+            None => {
+                slide_inplace(&mut board, m.dir).map_err(move_error)?;
+                for spawn in &m.spawned {
+                    board.spawn(spawn.cell, spawn.value).map_err(move_error)?;
+                }
+            },
+        }
+        if board.values() != &m.values[..] || board.score != m.score {
+            return Err(ReplayError::Board{index});
+        }
+    }
+
+    if board.score != record.final_score {
+        return Err(ReplayError::FinalScore{expected: record.final_score, actual: board.score});
+    }
+    Ok(board)
+}
+
+#[cfg(test)]
+mod record_tests {
+    use super::*;
+    use crate::algorithm::MaxFreeSpace;
+    use crate::simulate::{single_with_record, SimulationConfig};
+
+    #[test]
+    fn seeded_roundtrip() {
+        let (result, record) = single_with_record(&mut MaxFreeSpace, 42, &SimulationConfig::default()).unwrap();
+        assert_eq!(record.seed, Some(42));
+        assert_eq!(record.moves.len() as i32, result.moves);
+        assert_eq!(record.final_score, result.score);
+
+        let yaml = GameRecord::from_yaml(&record.to_yaml().unwrap()).unwrap();
+        assert_eq!(yaml, record);
+        let json = GameRecord::from_json(&record.to_json().unwrap()).unwrap();
+        assert_eq!(json, record);
+
+        let board = replay(&record).unwrap();
+        assert_eq!(board.score, result.score);
+
+        // Any tampering is caught
+        let mut bad = record.clone();
+        bad.moves[3].spawned[0].value ^= 6;
+        assert!(matches!(replay(&bad), Err(ReplayError::Spawn{index: 3, ..})));
+        let mut bad = record.clone();
+        bad.moves[5].score += 4;
+        assert_eq!(replay(&bad).err(), Some(ReplayError::Board{index: 5}));
+        let mut bad = record;
+        bad.seed = Some(43);
+        assert_eq!(replay(&bad).err(), Some(ReplayError::InitialBoard));
+    }
+
+    #[test]
+    fn custom_rules() {
+        let spawn = SpawnRules{values: vec![(2, 1), (4, 1)], tiles_per_move: 2, start_tiles: 3};
+        let config = SimulationConfig{spawn: spawn.clone(), ..SimulationConfig::default()};
+        let (_, record) = single_with_record(&mut MaxFreeSpace, 8, &config).unwrap();
+        assert_eq!(record.spawn, spawn);
+        assert_eq!(record.initial.iter().filter(|v| **v != 0).count(), 3);
+        assert_eq!(record.moves[0].spawned.len(), 2);
+        assert!(replay(&record).is_ok());
+
+        // Under the default rules the same seed gives a different game
+        let mut bad = record;
+        bad.spawn = SpawnRules::default();
+        assert!(replay(&bad).is_err());
+    }
+
+    #[test]
+    fn blockers_and_wildcards() {
+        let mut config = SimulationConfig::default();
+        config.game.blockers = 2;
+        config.game.wildcards = 1;
+        let (_, record) = single_with_record(&mut MaxFreeSpace, 5, &config).unwrap();
+        assert_eq!(record.initial.iter().filter(|v| **v == BLOCKER).count(), 2);
+        assert_eq!(record.initial.iter().filter(|v| **v == WILDCARD).count(), 1);
+        assert!(replay(&record).is_ok());
+    }
+
+    #[test]
+    fn unseeded_history() {
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut history = HistoryGame::new(GamePlayer::from_seed(29), GameOptions::default()).unwrap();
+        for turn in 0..30 {
+            let _ = history.play(dirs[turn % 4]);
+            if turn % 7 == 6 {
+                history.undo();
+            }
+        }
+        let record = GameRecord::from_history(&history, None);
+        assert_eq!(record.moves.len(), history.position());
+        let board = replay(&record).unwrap();
+        assert_eq!(board.values(), history.board().values());
+
+        let mut bad = record;
+        bad.moves[2].dir = match bad.moves[2].dir {
+            MoveDir::Up => MoveDir::Down,
+            MoveDir::Down => MoveDir::Up,
+            MoveDir::Left => MoveDir::Right,
+            MoveDir::Right => MoveDir::Left,
+        };
+        assert!(replay(&bad).is_err());
+    }
+}
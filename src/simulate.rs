@@ -1,88 +1,494 @@
 use super::gameplay::*;
+use super::merge::MergeRule;
+use super::algorithm::Strategy;
+use super::record::GameRecord;
+use super::threes::ThreesGame;
+use super::adversary::{Adversary, AdversarialGame};
 
 
 use serde::{Serialize, Deserialize};
 
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
 
-#[derive(Debug)]
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SingleRunResult {
+    // The game's seed, which with the strategy is enough to replay it
+    pub seed: u64,
     pub moves: i32,
     pub score: i32,
-    pub largest: i32
+    pub largest: i32,
+    // Whether the target tile was reached
+    pub won: bool,
+    // The position the game ended in
+    pub board: Board,
+}
+
+// Summary statistics over one quantity (e.g. score) across a set of games
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Summary {
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    // Keyed by percent, e.g. 5 -> the value that 5% of games fell below
+    pub percentiles: BTreeMap<u32, f64>,
+    // 95% bootstrap confidence interval for the mean
+    pub mean_ci_low: f64,
+    pub mean_ci_high: f64,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct BulkRunResult {
-    pub avg_moves: i32,
-    pub avg_score: i32,
+    pub runs: i32,
+    pub avg_moves: f64,
+    pub avg_score: f64,
+    // Fraction of games which reached the target tile
+    pub win_rate: f64,
+    pub score: Summary,
+    pub moves: Summary,
+    // Fraction of games whose largest tile was at least the key
+    pub reach_rates: BTreeMap<i32, f64>,
     pub score_cdf_x: Vec<f32>,
     pub score_cdf_y: Vec<f32>,
+    // Number of games by the rank of their largest tile among the merge
+    // rule's tiles (see MergeRule::tile_rank), e.g. 2 -> 0, 4 -> 1, 8 -> 2
+    // in the standard game
     pub largest_hist: Vec<i32>,
+    // Every game, in seed order, if SimulationConfig::keep_games was set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub games: Vec<SingleRunResult>,
+}
+
+// Settings for a set of simulated games
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub runs: i32,
+    // Game i is seeded with base_seed + i
+    pub base_seed: u64,
+    // Number of worker threads, 0 for one per core
+    pub threads: usize,
+    pub game: GameOptions,
+    // How tiles spawn, both in the games and in the strategy's own searches
+    pub spawn: SpawnRules,
+    // Keep each game's result in BulkRunResult::games, not just the summary
+    pub keep_games: bool,
+    // If set, places the tiles spawned in the games (but not the strategy's
+    // searches, which still expect random tiles), to find each strategy's
+    // worst case
+    pub adversary: Option<Box<dyn Adversary>>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> SimulationConfig {
+        // Games carry on past the target by default, so that the score
+        // statistics show how far each strategy can get
+        let game = GameOptions{keep_playing: true, ..GameOptions::default()};
+        SimulationConfig{runs: 100, base_seed: 0, threads: 0, game, spawn: SpawnRules::default(), keep_games: false, adversary: None}
+    }
 }
 
-pub fn single(algo: fn(&mut GamePlayer, &Board) -> MoveDir) -> SingleRunResult {
+impl SimulationConfig {
+    // The default settings, but for games where tiles merge by another rule,
+    // with the spawns usual for that rule
+    pub fn for_merge(merge: MergeRule) -> SimulationConfig {
+        let game = GameOptions{keep_playing: true, ..GameOptions::for_merge(merge)};
+        SimulationConfig{game, spawn: SpawnRules::for_merge(merge), ..SimulationConfig::default()}
+    }
+}
+
+// Mixed into a game's seed to get the seed for the strategy's own player, so
+// that the strategy can't disturb the tiles spawned in the real game
+const STRATEGY_SEED_MASK: u64 = 0x9E37_79B9_7F4A_7C15;
+
+pub fn single(strategy: &mut dyn Strategy, seed: u64, config: &SimulationConfig) -> Result<SingleRunResult, GameError> {
+    Ok(single_with_record(strategy, seed, config)?.0)
+}
+
+// Play one game as single does, also recording it. The record carries the
+// game's seed, so it can be checked with record::replay. Games against an
+// adversary, and games with MergeRule::Threes (played as ThreesGames, dealt
+// from a deck, with any adversary only choosing where each tile enters),
+// spawn tiles replay can't reproduce, so their records have the spawned
+// tiles but no seed. Fails if config.spawn isn't valid for the game's merge
+// rule.
+pub fn single_with_record(strategy: &mut dyn Strategy, seed: u64, config: &SimulationConfig) -> Result<(SingleRunResult, GameRecord), GameError> {
+    // The game and the strategy each get their own RNG. Strategies use their
+    // player to try out moves, so sharing one would make the tiles in the
+    // actual game depend on how much searching the strategy did.
+    let mut strategy_player = GamePlayer::from_seed(seed ^ STRATEGY_SEED_MASK).with_rules(config.spawn.clone(), config.game.merge)?;
+    let played = if config.game.merge == MergeRule::Threes {
+        let mut game = ThreesGame::from_seed(seed, config.game);
+        if let Some(adversary) = &config.adversary {
+            game = game.with_adversary(adversary.clone());
+        }
+        // The tiles come from the deck whatever config.spawn says, so record
+        // the rules of the game's own player, which match the deck
+        let record = GameRecord::new(config.game, game.game().rules().clone(), None, game.game().board());
+        play_out(&mut game, strategy, &mut strategy_player, seed, record)
+    } else if let Some(adversary) = &config.adversary {
+        let player = GamePlayer::from_seed(seed).with_rules(config.spawn.clone(), config.game.merge)?;
+        let mut game = AdversarialGame::new(player, config.game, adversary.clone())?;
+        let record = GameRecord::new(config.game, config.spawn.clone(), None, game.game().board());
+        play_out(&mut game, strategy, &mut strategy_player, seed, record)
+    } else {
+        let mut game = Game::new(GamePlayer::from_seed(seed).with_rules(config.spawn.clone(), config.game.merge)?, config.game)?;
+        let record = GameRecord::new(config.game, config.spawn.clone(), Some(seed), game.board());
+        play_out(&mut game, strategy, &mut strategy_player, seed, record)
+    };
+    Ok(played)
+}
+
+fn play_out(game: &mut dyn PlayedGame, strategy: &mut dyn Strategy, strategy_player: &mut GamePlayer, seed: u64, mut record: GameRecord) -> (SingleRunResult, GameRecord) {
     const MAX_INVALID: i32 = 20;
-    let mut result = SingleRunResult{moves: 0, score: 0, largest: 0};
     let mut invalid_count = 0;
-    let mut board = Board::init();
-    let mut player = GamePlayer::default();
-    while !board.stuck() && invalid_count < MAX_INVALID {
-        let req_move = algo(&mut player, &board);
+    strategy.reset();
+    while game.game().status() == GameStatus::InProgress && invalid_count < MAX_INVALID {
+        let req_move = match strategy.choose(strategy_player, game.game().board()) {
+            Some(dir) => dir,
+            None => break,
+        };
         // invalid moves are just ignored, but only a few times so that we don't get stuck. 
-        if let Ok(new_board) = player.play(&board, req_move) {
-            board = new_board;
-            invalid_count = 0;
-        } else {
-            invalid_count += 1;
+        match game.play(req_move) {
+            Ok(result) => {
+                record.push(req_move, &result, game.game().board());
+                invalid_count = 0;
+            },
+            Err(_) => invalid_count += 1,
         }
     }
-    result.score = board.score;
-    result.largest = *board.values.iter().max().unwrap();
-    result
+    let game = game.game();
+    let result = SingleRunResult{
+        seed,
+        moves: game.moves(),
+        score: game.board().score,
+        largest: game.board().largest(),
+        won: game.has_won(),
+        board: game.board().clone(),
+    };
+    (result, record)
 }
 
 
-pub fn bulk(algo: fn(&mut GamePlayer, &Board) -> MoveDir, n: i32) -> BulkRunResult {
-    const CDF_POINTS: i32 = 100;
+// Run config.runs games, seeding game i with base_seed + i so that every
+// algorithm run with the same base seed faces the same sequence of random
+// tiles.
+// Games are spread across worker threads, each with its own copy of the
+// strategy. Since every game is seeded on its own and starts with a reset
+// strategy, the results don't depend on the thread count.
+pub fn bulk(strategy: &dyn Strategy, config: &SimulationConfig) -> Result<BulkRunResult, GameError> {
+    // Checked up front, so that no worker has a game to fail
+    config.spawn.validate(config.game.merge)?;
+    let threads = if config.threads == 0 {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    } else {
+        config.threads
+    };
+    let n = config.runs.max(0) as usize;
+    let base_seed = config.base_seed;
+    let next_game = AtomicUsize::new(0);
 
-    let mut results: Vec<SingleRunResult> = Vec::new();
-    for _ in 0..n {
-        results.push(single(algo));
+    let mut indexed: Vec<(usize, SingleRunResult)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(n.max(1))).map(|_| {
+            let mut worker_strategy = strategy.clone_box();
+            let next_game = &next_game;
+            scope.spawn(move || {
+                let mut out = Vec::new();
+                loop {
+                    let i = next_game.fetch_add(1, Ordering::Relaxed);
+                    if i >= n {
+                        break;
+                    }
+                    let result = single(worker_strategy.as_mut(), base_seed.wrapping_add(i as u64), config);
+                    out.push((i, result.expect("spawn rules were validated")));
+                }
+                out
+            })
+        }).collect();
+        workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+    });
+
+    indexed.sort_by_key(|(i, _)| *i);
+    let results: Vec<SingleRunResult> = indexed.into_iter().map(|(_, r)| r).collect();
+    let mut summary = summarize_runs(&results, base_seed, config.game.merge);
+    if config.keep_games {
+        summary.games = results;
     }
-    let max_score = results.iter().max_by(|a, b| { a.score.cmp(&b.score) }).unwrap().score;
-    results.sort_by(|a, b| { a.score.cmp(&b.score)});
+    Ok(summary)
+}
+
+// Number of resamples used for bootstrap confidence intervals
+const BOOTSTRAP_SAMPLES: usize = 1000;
+const PERCENTILES: [u32; 4] = [5, 25, 75, 95];
+// Ranks (see MergeRule::tile) of the tiles reported in reach_rates: every
+// tile from MIN_REACH_RANK up to REACH_RANK, or the largest tile seen if that
+// is bigger. These are 512 and 2048 in the standard game.
+const MIN_REACH_RANK: usize = 8;
+const REACH_RANK: usize = 10;
+
+// Statistics over games whose tiles merged by the given rule
+pub fn summarize_runs(results: &[SingleRunResult], seed: u64, merge: MergeRule) -> BulkRunResult {
+    const CDF_POINTS: i32 = 100;
+
+    // The bootstrap resampling is seeded too, so a report is reproducible
+    let mut rng = SmallRng::seed_from_u64(seed);
 
-    let cdf_step = max_score as f32 / CDF_POINTS as f32;
-    let mut x = cdf_step;
-    let mut y = 0.0;
-    let mut i = 0;
+    let mut scores: Vec<f64> = results.iter().map(|r| r.score as f64).collect();
+    let mut moves: Vec<f64> = results.iter().map(|r| r.moves as f64).collect();
+    let score = summarize(&mut scores, &mut rng);
+    let moves = summarize(&mut moves, &mut rng);
+
+    // The score CDF is sampled at evenly spaced points up to the max score,
+    // giving the fraction of games which scored at or below each point.
+    // scores is sorted by summarize, so that is found by bisection.
     let mut cdf_x_values: Vec<f32> = Vec::new();
     let mut cdf_y_values: Vec<f32> = Vec::new();
-    while x <= max_score as f32{
-        while (results[i].score as f32) < x {
-            y += 1.0 / results.len() as f32;
-            i += 1;
+    if !scores.is_empty() {
+        let max_score = score.max;
+        // If every game scored 0, the CDF is just a step at 0
+        let points = if max_score > 0.0 { CDF_POINTS } else { 1 };
+        for k in 1..=points {
+            let x = max_score * k as f64 / points as f64;
+            let below = scores.partition_point(|s| *s <= x);
+            cdf_x_values.push(x as f32);
+            cdf_y_values.push((below as f64 / scores.len() as f64) as f32);
         }
-        cdf_x_values.push(x);
-        cdf_y_values.push(y);
-        x += cdf_step;
     }
 
     let mut largest_hist: Vec<i32> = Vec::new();
     for r in results {
-        // Expect: 2 -> 0, 4 -> 1, 8 -> 2, etc
-        let bin = 30 - r.largest.leading_zeros();
-        if (bin as usize) >= largest_hist.len() {
-            largest_hist.resize_with((bin + 1) as usize, Default::default);
+        // A game can end with no tile at all (e.g. a board of blockers),
+        // which counts with the smallest tile
+        let bin = merge.tile_rank(r.largest);
+        if bin >= largest_hist.len() {
+            largest_hist.resize_with(bin + 1, Default::default);
         }
-        largest_hist[bin as usize] += 1;
+        largest_hist[bin] += 1;
+    }
+
+    let mut reach_rates = BTreeMap::new();
+    let top_rank = results.iter().map(|r| merge.tile_rank(r.largest)).max().unwrap_or(0).max(REACH_RANK);
+    for tile in (MIN_REACH_RANK..=top_rank).map_while(|rank| merge.tile(rank)) {
+        let reached = results.iter().filter(|r| r.largest >= tile).count();
+        reach_rates.insert(tile, reached as f64 / results.len().max(1) as f64);
     }
 
+    let wins = results.iter().filter(|r| r.won).count();
+
     BulkRunResult{
-        avg_moves: 0,
-        avg_score: 0,
+        runs: results.len() as i32,
+        avg_moves: moves.mean,
+        avg_score: score.mean,
+        win_rate: wins as f64 / results.len().max(1) as f64,
+        score,
+        moves,
+        reach_rates,
         score_cdf_x: cdf_x_values,
         score_cdf_y: cdf_y_values,
-        largest_hist
+        largest_hist,
+        games: Vec::new(),
+    }
+}
+
+// Compute summary statistics, sorting values in the process
+fn summarize(values: &mut [f64], rng: &mut SmallRng) -> Summary {
+    if values.is_empty() {
+        return Summary::default();
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let n = values.len();
+    let mean = mean(values);
+    let variance = if n > 1 {
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1) as f64
+    } else {
+        0.0
+    };
+
+    // Bootstrap the mean: resample the games with replacement many times, and
+    // take the middle 95% of the resampled means as the interval
+    let mut means: Vec<f64> = (0..BOOTSTRAP_SAMPLES)
+        .map(|_| (0..n).map(|_| values[rng.gen_range(0, n)]).sum::<f64>() / n as f64)
+        .collect();
+    means.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    Summary{
+        mean,
+        median: percentile(values, 50.0),
+        stddev: variance.sqrt(),
+        min: values[0],
+        max: values[n - 1],
+        percentiles: PERCENTILES.iter().map(|p| (*p, percentile(values, *p as f64))).collect(),
+        mean_ci_low: percentile(&means, 2.5),
+        mean_ci_high: percentile(&means, 97.5),
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// Percentile of sorted values, interpolating linearly between the closest ranks
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = percent / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let frac = rank - lower as f64;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * frac
+}
+
+#[cfg(test)]
+mod simulate_tests {
+    use super::*;
+
+    fn run(score: i32, moves: i32, largest: i32) -> SingleRunResult {
+        SingleRunResult{seed: 0, score, moves, largest, won: largest >= 2048, board: Board::default()}
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&values, 50.0), 3.0);
+        assert_eq!(percentile(&values, 100.0), 5.0);
+        assert_eq!(percentile(&values, 25.0), 2.0);
+        assert_eq!(percentile(&values, 12.5), 1.5);
+    }
+
+    #[test]
+    fn test_summarize_runs() {
+        let results = vec![run(100, 10, 16), run(300, 30, 1024), run(200, 20, 512), run(400, 40, 2048)];
+        let r = summarize_runs(&results, 0, MergeRule::Standard);
+        assert_eq!(r.runs, 4);
+        assert_eq!(r.avg_score, 250.0);
+        assert_eq!(r.avg_moves, 25.0);
+        assert_eq!(r.score.median, 250.0);
+        assert_eq!(r.score.min, 100.0);
+        assert_eq!(r.score.max, 400.0);
+        assert!((r.score.stddev - 129.0994).abs() < 0.01);
+        assert!(r.score.mean_ci_low <= r.score.mean && r.score.mean <= r.score.mean_ci_high);
+        assert!(r.score.mean_ci_low >= 100.0 && r.score.mean_ci_high <= 400.0);
+        assert_eq!(r.reach_rates[&512], 0.75);
+        assert_eq!(r.reach_rates[&1024], 0.5);
+        assert_eq!(r.reach_rates[&2048], 0.25);
+        assert_eq!(r.win_rate, 0.25);
+        assert_eq!(*r.score_cdf_y.last().unwrap(), 1.0);
+        assert_eq!(r.largest_hist, vec![0, 0, 0, 1, 0, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn summarize_under_merge_rules() {
+        // Games ending with no tile above 1, or none at all, used to underflow
+        // the histogram
+        let r = summarize_runs(&[run(0, 0, 0), run(0, 0, 1), run(4, 1, 4)], 0, MergeRule::Standard);
+        assert_eq!(r.largest_hist, vec![2, 1]);
+        // Each Threes tile gets its own bin, and the reach rates are for its tiles
+        let results = vec![run(10, 5, 2), run(10, 5, 3), run(10, 5, 6), run(10, 5, 384)];
+        let r = summarize_runs(&results, 0, MergeRule::Threes);
+        assert_eq!(r.largest_hist, vec![0, 1, 1, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(r.reach_rates.keys().copied().collect::<Vec<i32>>(), vec![192, 384, 768]);
+        assert_eq!(r.reach_rates[&384], 0.25);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bulk_independent_of_threads() {
+        let strategy = crate::algorithm::MaxFreeSpace;
+        let mut config = SimulationConfig{runs: 12, base_seed: 7, threads: 1, ..SimulationConfig::default()};
+        let serial = serde_yaml::to_string(&bulk(&strategy, &config).unwrap()).unwrap();
+        config.threads = 4;
+        let parallel = serde_yaml::to_string(&bulk(&strategy, &config).unwrap()).unwrap();
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn games_roundtrip() {
+        let strategy = crate::algorithm::MaxFreeSpace;
+        let mut config = SimulationConfig{runs: 3, base_seed: 5, threads: 2, ..SimulationConfig::default()};
+        assert!(bulk(&strategy, &config).unwrap().games.is_empty());
+
+        config.keep_games = true;
+        let result = bulk(&strategy, &config).unwrap();
+        let seeds: Vec<u64> = result.games.iter().map(|g| g.seed).collect();
+        assert_eq!(seeds, vec![5, 6, 7]);
+        assert_eq!(result.games[1], single(&mut crate::algorithm::MaxFreeSpace, 6, &config).unwrap());
+        assert!(result.games.iter().all(|g| g.board.stuck() && g.board.score == g.score));
+
+        let read: BulkRunResult = serde_yaml::from_str(&serde_yaml::to_string(&result).unwrap()).unwrap();
+        assert_eq!(read.games, result.games);
+        assert_eq!(read.avg_score, result.avg_score);
+
+        config.spawn.values = vec![(2, 0)];
+        assert_eq!(bulk(&strategy, &config).err(), Some(GameError::InvalidSpawnRules));
+        // The standard spawns aren't all tiles in a Fibonacci game
+        let config = SimulationConfig{game: GameOptions{merge: MergeRule::Fibonacci, ..GameOptions::default()}, ..SimulationConfig::default()};
+        assert_eq!(bulk(&strategy, &config).err(), Some(GameError::InvalidSpawnRules));
+        assert_eq!(single(&mut crate::algorithm::MaxFreeSpace, 1, &config).err(), Some(GameError::InvalidSpawnRules));
+    }
+
+    #[test]
+    fn bulk_under_merge_rules() {
+        let strategy = crate::algorithm::MaxFreeSpace;
+        for merge in MergeRule::ALL.iter() {
+            let config = SimulationConfig{runs: 4, threads: 2, keep_games: true, ..SimulationConfig::for_merge(*merge)};
+            let result = bulk(&strategy, &config).unwrap();
+            assert_eq!(result.runs, 4);
+            for game in result.games.iter() {
+                assert_eq!(game.board.merge, *merge);
+                assert!(game.board.stuck());
+                assert!(game.board.values().iter().all(|v| merge.is_tile(*v)));
+            }
+            let (result, record) = single_with_record(&mut crate::algorithm::MaxFreeSpace, 3, &config).unwrap();
+            assert_eq!(crate::record::replay(&record).unwrap(), result.board);
+            // Threes games come from the deck, which replay can't reproduce
+            assert_eq!(record.seed.is_none(), *merge == MergeRule::Threes);
+        }
+    }
+
+    #[test]
+    fn bulk_against_adversary() {
+        use crate::adversary::WorstCase;
+        use crate::algorithm::ScoreFunction;
+        let strategy = crate::algorithm::MaxFreeSpace;
+        let random = SimulationConfig{runs: 6, threads: 2, ..SimulationConfig::default()};
+        let adversary = WorstCase{score_fn: ScoreFunction::FreeSpaceWithSortedness};
+        let config = SimulationConfig{adversary: Some(Box::new(adversary)), ..random.clone()};
+        let worst = bulk(&strategy, &config).unwrap();
+        assert_eq!(worst.runs, 6);
+        assert!(worst.avg_score < bulk(&strategy, &random).unwrap().avg_score);
+        // The games are still the same on any number of threads
+        let single_thread = SimulationConfig{threads: 1, keep_games: true, ..config.clone()};
+        let multi_thread = SimulationConfig{threads: 3, keep_games: true, ..config.clone()};
+        assert_eq!(bulk(&strategy, &single_thread).unwrap().games, bulk(&strategy, &multi_thread).unwrap().games);
+        let (result, record) = single_with_record(&mut crate::algorithm::MaxFreeSpace, 2, &config).unwrap();
+        assert!(record.seed.is_none());
+        assert_eq!(crate::record::replay(&record).unwrap(), result.board);
+
+        // Threes tiles still come from the deck, and the record has the rules
+        // matching it rather than the configured ones
+        let threes = SimulationConfig{
+            spawn: SpawnRules{values: vec![(3, 1)], ..SpawnRules::for_merge(MergeRule::Threes)},
+            adversary: config.adversary.clone(),
+            ..SimulationConfig::for_merge(MergeRule::Threes)
+        };
+        let (result, record) = single_with_record(&mut crate::algorithm::MaxFreeSpace, 2, &threes).unwrap();
+        assert_eq!(record.spawn, SpawnRules::for_merge(MergeRule::Threes));
+        assert!(record.moves.iter().any(|m| m.spawned[0].value < 3));
+        assert_eq!(crate::record::replay(&record).unwrap(), result.board);
+    }
+
+    #[test]
+    fn test_all_zero_scores() {
+        // Used to panic building the CDF
+        let results = vec![run(0, 0, 2), run(0, 0, 2)];
+        let r = summarize_runs(&results, 0, MergeRule::Standard);
+        assert_eq!(r.score_cdf_x, vec![0.0]);
+        assert_eq!(r.score_cdf_y, vec![1.0]);
+        assert_eq!(r.avg_score, 0.0);
+    }
+}
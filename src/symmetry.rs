@@ -0,0 +1,218 @@
+use super::gameplay::*;
+
+use serde::{Serialize, Deserialize};
+
+// The 8 symmetries of a square board. A move on a transformed board does
+// the same thing as the mapped move (see map_dir) on the original, so
+// positions related by a transform are strategically identical. Half of them
+// swap the rows and columns, so only the other four keep a board that isn't
+// square the same shape.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Transform {
+    Identity,
+    // Rotations are clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // Mirror left to right
+    FlipHorizontal,
+    // Mirror top to bottom
+    FlipVertical,
+    // Mirror about the main (top left to bottom right) diagonal
+    Transpose,
+    // Mirror about the other diagonal
+    AntiTranspose,
+}
+
+impl Transform {
+    pub const ALL: [Transform; 8] = [
+        Transform::Identity,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::FlipHorizontal,
+        Transform::FlipVertical,
+        Transform::Transpose,
+        Transform::AntiTranspose,
+    ];
+
+    // Where the cell at (row, col) of a rows x cols board ends up
+    pub fn map_cell(self, row: usize, col: usize, rows: usize, cols: usize) -> (usize, usize) {
+        match self {
+            Transform::Identity => (row, col),
+            Transform::Rotate90 => (col, rows - 1 - row),
+            Transform::Rotate180 => (rows - 1 - row, cols - 1 - col),
+            Transform::Rotate270 => (cols - 1 - col, row),
+            Transform::FlipHorizontal => (row, cols - 1 - col),
+            Transform::FlipVertical => (rows - 1 - row, col),
+            Transform::Transpose => (col, row),
+            Transform::AntiTranspose => (cols - 1 - col, rows - 1 - row),
+        }
+    }
+
+    // True if the transformed board has rows and columns swapped
+    pub fn swaps_dims(self) -> bool {
+        matches!(self, Transform::Rotate90 | Transform::Rotate270 | Transform::Transpose | Transform::AntiTranspose)
+    }
+
+    // The move on the transformed board matching dir on the original
+    pub fn map_dir(self, dir: MoveDir) -> MoveDir {
+        use MoveDir::*;
+        // As (Up, Down, Left, Right) map to
+        let mapped = match self {
+            Transform::Identity => [Up, Down, Left, Right],
+            Transform::Rotate90 => [Right, Left, Up, Down],
+            Transform::Rotate180 => [Down, Up, Right, Left],
+            Transform::Rotate270 => [Left, Right, Down, Up],
+            Transform::FlipHorizontal => [Up, Down, Right, Left],
+            Transform::FlipVertical => [Down, Up, Left, Right],
+            Transform::Transpose => [Left, Right, Up, Down],
+            Transform::AntiTranspose => [Right, Left, Down, Up],
+        };
+        match dir {
+            Up => mapped[0],
+            Down => mapped[1],
+            Left => mapped[2],
+            Right => mapped[3],
+        }
+    }
+
+    // The transform which undoes this one
+    pub fn inverse(self) -> Transform {
+        match self {
+            Transform::Rotate90 => Transform::Rotate270,
+            Transform::Rotate270 => Transform::Rotate90,
+            other => other,
+        }
+    }
+}
+
+impl Board {
+    pub fn transform(&self, t: Transform) -> Board {
+        let mut out = if t.swaps_dims() {
+            Board::with_merge(self.cols(), self.rows(), self.merge)
+        } else {
+            Board::with_merge(self.rows(), self.cols(), self.merge)
+        };
+        out.score = self.score;
+        for row in 0..self.rows() {
+            for col in 0..self.cols() {
+                let (r, c) = t.map_cell(row, col, self.rows(), self.cols());
+                out.set(r * out.cols() + c, self.values()[row * self.cols() + col]);
+            }
+        }
+        out
+    }
+
+    pub fn rotate_cw(&self) -> Board {
+        self.transform(Transform::Rotate90)
+    }
+
+    pub fn reflect_horizontal(&self) -> Board {
+        self.transform(Transform::FlipHorizontal)
+    }
+
+    pub fn reflect_vertical(&self) -> Board {
+        self.transform(Transform::FlipVertical)
+    }
+
+    pub fn transpose(&self) -> Board {
+        self.transform(Transform::Transpose)
+    }
+
+    // The same representative for every board in a symmetry class: whichever
+    // of the 8 transforms of this board has the smallest values. Also returns
+    // the transform that gives it, so a move chosen on the canonical board can
+    // be mapped back with transform.inverse().map_dir(). Boards that aren't
+    // square only use the transforms which keep their shape.
+    pub fn canonical(&self) -> (Board, Transform) {
+        let square = self.rows() == self.cols();
+        let mut best = (self.clone(), Transform::Identity);
+        for t in Transform::ALL.iter().skip(1).filter(|t| square || !t.swaps_dims()) {
+            let candidate = self.transform(*t);
+            if candidate.values() < best.0.values() {
+                best = (candidate, *t);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod symmetry_tests {
+    use super::*;
+
+    const DIRS: [MoveDir; 4] = [MoveDir::Up, MoveDir::Down, MoveDir::Left, MoveDir::Right];
+
+    #[test]
+    fn test_transforms() {
+        let b: Board = "2,4,.,./.,.,.,./.,.,.,./.,.,.,8".parse().unwrap();
+        assert_eq!(b.rotate_cw().to_compact(), ".,.,.,2/.,.,.,4/.,.,.,./8,.,.,.");
+        assert_eq!(b.reflect_horizontal().to_compact(), ".,.,4,2/.,.,.,./.,.,.,./8,.,.,.");
+        assert_eq!(b.reflect_vertical().to_compact(), ".,.,.,8/.,.,.,./.,.,.,./2,4,.,.");
+        assert_eq!(b.transpose().to_compact(), "2,.,.,./4,.,.,./.,.,.,./.,.,.,8");
+        assert_eq!(b.rotate_cw().rotate_cw(), b.transform(Transform::Rotate180));
+        assert_eq!(b.rotate_cw().rotate_cw().rotate_cw().rotate_cw(), b);
+        for t in Transform::ALL.iter() {
+            assert_eq!(b.transform(*t).transform(t.inverse()), b);
+        }
+    }
+
+    #[test]
+    fn moves_commute() {
+        // Moving then transforming is the same as transforming then making the
+        // mapped move
+        let mut player = GamePlayer::from_seed(3);
+        let mut b = player.new_board(StartPosition::Random);
+        for turn in 0..200 {
+            for t in Transform::ALL.iter() {
+                for dir in DIRS.iter() {
+                    let moved = b.slide(*dir).map(|(after, _)| after.transform(*t));
+                    let mapped = b.transform(*t).slide(t.map_dir(*dir)).map(|(after, _)| after);
+                    assert_eq!(moved.ok(), mapped.ok(), "{:?} {:?}", t, dir);
+                }
+            }
+            if b.stuck() {
+                b = player.new_board(StartPosition::Random);
+            } else {
+                let _ = player.play_inplace(&mut b, DIRS[turn % 4]);
+            }
+        }
+    }
+
+    #[test]
+    fn other_sizes() {
+        let b: Board = "2,4,.,.,.,./.,.,.,.,.,./.,.,.,.,.,8".parse().unwrap();
+        let rotated = b.rotate_cw();
+        assert_eq!((rotated.rows(), rotated.cols()), (6, 3));
+        assert_eq!(rotated.to_compact(), ".,.,2/.,.,4/.,.,./.,.,./.,.,./8,.,.");
+        for t in Transform::ALL.iter() {
+            assert_eq!(b.transform(*t).transform(t.inverse()), b);
+            for dir in DIRS.iter() {
+                let moved = b.slide(*dir).map(|(after, _)| after.transform(*t));
+                let mapped = b.transform(*t).slide(t.map_dir(*dir)).map(|(after, _)| after);
+                assert_eq!(moved.ok(), mapped.ok(), "{:?} {:?}", t, dir);
+            }
+        }
+        // Only shape preserving transforms count for the canonical form
+        let (canonical, t) = b.canonical();
+        assert!(!t.swaps_dims());
+        assert_eq!((canonical.rows(), canonical.cols()), (3, 6));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let b: Board = "2,4,.,./.,.,.,./.,16,.,./.,.,.,8".parse().unwrap();
+        let (canonical, t) = b.canonical();
+        assert_eq!(b.transform(t), canonical);
+        for other in Transform::ALL.iter() {
+            assert_eq!(b.transform(*other).canonical().0, canonical);
+        }
+        // Map a move on the canonical board back to the original
+        for dir in DIRS.iter() {
+            let back = t.inverse().map_dir(*dir);
+            assert_eq!(canonical.slide(*dir).ok().map(|(a, _)| a.transform(t.inverse())),
+                       b.slide(back).ok().map(|(a, _)| a));
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use super::gameplay::*;
+use super::merge::MergeRule;
+use super::adversary::Adversary;
+
+use serde::{Serialize, Deserialize};
+
+use rand::{Rng, RngCore, SeedableRng};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+
+// The rules of Threes!, as a variant of the standard game. Boards under
+// MergeRule::Threes follow them for moves, valid moves and stuck, so every
+// strategy can play. The differences from the standard game are:
+//
+// - Each move shifts the tiles in a line by at most one cell. The first tile
+//   from the edge which can move (into an empty cell, or by merging with the
+//   tile ahead of it) does, along with every tile behind it.
+// - A 1 and a 2 merge into a 3, and equal tiles of 3 or more double. Tiles
+//   are 1, 2 and 3 times a power of two.
+// - The new tile enters on the edge opposite the move, in one of the lines
+//   which moved.
+// - Tiles come from a shuffled deck of four each of 1, 2 and 3, which is
+//   refilled when it runs out. Once the board has a 48 there is sometimes a
+//   bonus tile instead, and the player can see what the next tile will be.
+//
+// ThreesGame plays by all of these. Strategies trying out moves with their
+// own GamePlayer don't know the state of the deck, so play_inplace instead
+// draws new tiles from the player's spawn rules, which by default give 1, 2
+// and 3 in the deck's proportions.
+
+// Cards of each value in a full deck
+const DECK_REPEATS: usize = 4;
+// Bonus tiles are possible once the largest tile is at least this
+const BONUS_MIN_LARGEST: i32 = 48;
+// Chance of a bonus tile when one is possible, as 1 in this
+const BONUS_ODDS: u32 = 21;
+// Tiles dealt at the start of a game on the standard board
+const START_TILES: usize = 9;
+
+// Points for a tile at the end of a real Threes! game: 3 for a 3, and three
+// times as much for each doubling after that. 1s and 2s are worth nothing.
+pub fn tile_points(value: i32) -> i32 {
+    if value < 3 {
+        return 0;
+    }
+    3i32.saturating_pow((value / 3).trailing_zeros() + 1)
+}
+
+// Shift one line a cell towards its edge, as a move does. line holds the
+// line's cells in order from the edge, with 0 for empty. Returns the score
+// gained, which as in the other variants is the value of any merged tile, or
+// None if nothing in the line can move.
+pub fn slide_line(line: &mut [i32]) -> Option<i32> {
+    let first = first_move(line)?;
+    let mut add_score = 0;
+    if line[first - 1] == 0 {
+        line[first - 1] = line[first];
+    } else {
+        // first_move only stops at a tile which merges
+        let merged = MergeRule::Threes.merge(&line[first - 1..=first]).unwrap();
+        line[first - 1] = merged;
+        add_score = merged;
+    }
+    for pos in first + 1..line.len() {
+        line[pos - 1] = line[pos];
+    }
+    *line.last_mut().unwrap() = 0;
+    Some(add_score)
+}
+
+// Position of the first tile in the line (ordered from the edge, as for
+// slide_line) which can move, if any
+pub fn first_move(line: &[i32]) -> Option<usize> {
+    (1..line.len()).find(|pos| {
+        let (ahead, here) = (line[pos - 1], line[*pos]);
+        here != 0 && (ahead == 0 || MergeRule::Threes.merge(&[ahead, here]).is_some())
+    })
+}
+
+// Slide a Threes board, adding any merged tiles to its score.
+// Returns true if anything moved.
+pub(crate) fn slide_values(b: &mut Board, dir: MoveDir) -> bool {
+    let mut changed = false;
+    for n in 0..b.line_count(dir) {
+        // Each run of cells between blockers shifts on its own
+        for cells in b.line_segments(dir, n) {
+            let mut line: Vec<i32> = cells.iter().map(|cell| b.values()[*cell]).collect();
+            if let Some(add_score) = slide_line(&mut line) {
+                for (cell, value) in cells.iter().zip(line) {
+                    b.set(*cell, value);
+                }
+                b.score += add_score;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// The cells a new tile can enter after a move from before to after: the far
+// end of each line which moved, or with blockers, of each run of cells
+// between them which moved
+pub fn entry_cells(before: &Board, after: &Board, dir: MoveDir) -> Vec<usize> {
+    (0..after.line_count(dir))
+        .flat_map(|n| after.line_segments(dir, n))
+        .filter(|cells| cells.iter().any(|cell| before.values()[*cell] != after.values()[*cell]))
+        .map(|cells| *cells.last().unwrap())
+        .collect()
+}
+
+// A move for a strategy's search: slide, then add a tile drawn from the
+// spawn rules in one of the entry cells. Only one tile enters per move,
+// whatever rules.tiles_per_move says.
+pub fn play_inplace(b: &mut Board, dir: MoveDir, rng: &mut SmallRng, rules: &SpawnRules) -> Result<MoveResult, GameError> {
+    let before = b.clone();
+    let score = slide_inplace(b, dir)?;
+    let value = rules.pick_value(rng.next_u64() >> 32);
+    let spawn = enter_tile(&before, b, dir, value, rng);
+    Ok(MoveResult{score, spawned: vec![spawn]})
+}
+
+// Put a tile of the given value in a random entry cell
+fn enter_tile(before: &Board, after: &mut Board, dir: MoveDir, value: i32, rng: &mut SmallRng) -> Spawn {
+    let cells = entry_cells(before, after, dir);
+    let cell = *cells.choose(rng).unwrap();
+    after.set(cell, value);
+    Spawn{cell, value}
+}
+
+// What the player is shown of the next tile
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NextTile {
+    // A 1, 2 or 3 from the deck
+    Tile(i32),
+    // A bonus tile, which will be one of the doublings from low to high
+    Bonus { low: i32, high: i32 },
+}
+
+// The deck the regular tiles are dealt from
+#[derive(Clone, Debug, Default)]
+pub struct Deck {
+    cards: Vec<i32>,
+}
+
+impl Deck {
+    pub fn draw(&mut self, rng: &mut SmallRng) -> i32 {
+        if self.cards.is_empty() {
+            for value in 1..=3 {
+                self.cards.extend(std::iter::repeat_n(value, DECK_REPEATS));
+            }
+            self.cards.shuffle(rng);
+        }
+        self.cards.pop().unwrap()
+    }
+
+    // Cards left before the deck is refilled
+    pub fn remaining(&self) -> usize {
+        self.cards.len()
+    }
+}
+
+// A game of Threes! on its own deck. As with AdversarialGame, the board,
+// moves and status are kept by a Game, plus the hint for the next tile.
+pub struct ThreesGame {
+    game: Game,
+    rng: SmallRng,
+    deck: Deck,
+    next: i32,
+    // If set, chooses which entry cell each dealt tile goes in
+    adversary: Option<Box<dyn Adversary>>,
+}
+
+impl ThreesGame {
+    // A new game whose tiles all come from one seed. Threes always starts
+    // with dealt tiles (nine on the standard board), so options.start and
+    // options.merge are ignored. Any blockers and wildcards go down first.
+    pub fn from_seed(seed: u64, options: GameOptions) -> ThreesGame {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut board = Board::with_merge(options.rows, options.cols, MergeRule::Threes);
+        board.add_special_cells(&options, &mut rng);
+        let mut deck = Deck::default();
+        let empty: Vec<usize> = (0..board.values().len()).filter(|cell| board.values()[*cell] == 0).collect();
+        let dealt = (board.values().len() * START_TILES / (DEFAULT_SIZE * DEFAULT_SIZE)).min(empty.len());
+        for n in rand::seq::index::sample(&mut rng, empty.len(), dealt).into_iter() {
+            board.set(empty[n], deck.draw(&mut rng));
+        }
+        ThreesGame::start(board, seed, rng, deck, options)
+    }
+
+    // Pick up a game from an arbitrary Threes board, with a full deck and
+    // the tiles to come drawn from the seed
+    pub fn with_board(board: Board, seed: u64, options: GameOptions) -> ThreesGame {
+        ThreesGame::start(board, seed, SmallRng::seed_from_u64(seed), Deck::default(), options)
+    }
+
+    fn start(board: Board, seed: u64, rng: SmallRng, deck: Deck, options: GameOptions) -> ThreesGame {
+        // The game's own player never spawns anything, as every tile comes
+        // from the deck, but its rules are the deck's proportions
+        let player = GamePlayer::from_seed(seed).with_rules(SpawnRules::for_merge(MergeRule::Threes), MergeRule::Threes)
+            .expect("usual spawn rules are valid");
+        let mut game = ThreesGame{game: Game::with_board(board, player, options), rng, deck, next: 0, adversary: None};
+        game.next = game.draw();
+        game
+    }
+
+    // The same game, but with the adversary choosing where each tile enters.
+    // The deck still decides what the tile is.
+    pub fn with_adversary(self, adversary: Box<dyn Adversary>) -> ThreesGame {
+        ThreesGame{adversary: Some(adversary), ..self}
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn deck(&self) -> &Deck {
+        &self.deck
+    }
+
+    // The hint for the tile the next move will bring in
+    pub fn next_tile(&self) -> NextTile {
+        if self.next <= 3 {
+            NextTile::Tile(self.next)
+        } else {
+            NextTile::Bonus{low: 6, high: self.game.board().largest() / 8}
+        }
+    }
+
+    // Points the board would score if the game ended now, which is how real
+    // Threes! games are scored (see tile_points)
+    pub fn points(&self) -> i32 {
+        self.game.board().values().iter().map(|v| tile_points(*v)).sum()
+    }
+
+    pub fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        if self.game.status() != GameStatus::InProgress {
+            return Err(GameError::GameOver);
+        }
+        let before = self.game.board();
+        let mut board = before.clone();
+        let score = slide_inplace(&mut board, dir)?;
+        let spawn = match self.adversary.as_mut() {
+            Some(adversary) => {
+                let cells = entry_cells(before, &board, dir);
+                let dealt = SpawnRules{values: vec![(self.next, 1)], ..SpawnRules::for_merge(MergeRule::Threes)};
+                let spawn = adversary.choose_spawn(&board, &cells, &dealt);
+                board.set(spawn.cell, spawn.value);
+                spawn
+            },
+            None => enter_tile(before, &mut board, dir, self.next, &mut self.rng),
+        };
+        let moves = self.game.moves() + 1;
+        self.game.restore(board, moves);
+        self.next = self.draw();
+        Ok(MoveResult{score, spawned: vec![spawn]})
+    }
+
+    // The next tile to come in, from the deck or occasionally a bonus tile
+    // between 6 and an eighth of the largest tile
+    fn draw(&mut self) -> i32 {
+        let largest = self.game.board().largest();
+        if largest >= BONUS_MIN_LARGEST && self.rng.gen_range(0, BONUS_ODDS) == 0 {
+            let choices = (largest / 8 / 6).trailing_zeros() + 1;
+            return 6 << self.rng.gen_range(0, choices);
+        }
+        self.deck.draw(&mut self.rng)
+    }
+}
+
+impl PlayedGame for ThreesGame {
+    fn game(&self) -> &Game {
+        &self.game
+    }
+
+    fn play(&mut self, dir: MoveDir) -> Result<MoveResult, GameError> {
+        ThreesGame::play(self, dir)
+    }
+
+    fn restore(&mut self, board: Board, moves: i32) {
+        self.game.restore(board, moves)
+    }
+}
+
+#[cfg(test)]
+mod threes_tests {
+    use super::*;
+
+    fn board(text: &str) -> Board {
+        Board::parse(text, MergeRule::Threes).unwrap()
+    }
+
+    #[test]
+    fn test_slide_line() {
+        let slid = |mut line: Vec<i32>| slide_line(&mut line).map(|score| (line, score));
+        assert_eq!(slid(vec![3, 0, 1, 2]), Some((vec![3, 1, 2, 0], 0)));
+        assert_eq!(slid(vec![1, 2, 3, 3]), Some((vec![3, 3, 3, 0], 3)));
+        assert_eq!(slid(vec![6, 6, 0, 0]), Some((vec![12, 0, 0, 0], 12)));
+        assert_eq!(slid(vec![0, 0, 0, 1]), Some((vec![0, 0, 1, 0], 0)));
+        assert_eq!(slid(vec![1, 1, 2, 2]), Some((vec![1, 3, 2, 0], 3)));
+        assert_eq!(slid(vec![1, 1, 3, 6]), None);
+        assert_eq!(slid(vec![3, 6, 12, 24]), None);
+        assert_eq!(tile_points(1), 0);
+        assert_eq!(tile_points(3), 3);
+        assert_eq!(tile_points(48), 243);
+    }
+
+    #[test]
+    fn test_moves() {
+        let b = board("
+            1 2 . 3
+            . 1 1 .
+            3 3 6 6
+            2 . . .");
+        let (after, add_score) = b.slide(MoveDir::Left).unwrap();
+        assert_eq!(after.to_compact(), "3,.,3,./1,1,.,./6,6,6,./2,.,.,.");
+        assert_eq!(add_score, 3 + 6);
+        assert_eq!(entry_cells(&b, &after, MoveDir::Left), vec![3, 7, 11]);
+        assert!(!b.stuck());
+        assert!(b.to_bitboard().is_none());
+        let full = board("1,1,3,6/6,3,12,3/1,1,24,6/3,6,3,12");
+        assert!(full.stuck());
+        assert!(!full.is_valid_move(MoveDir::Left));
+    }
+
+    #[test]
+    fn test_game() {
+        let options = GameOptions{keep_playing: true, ..GameOptions::default()};
+        let mut game = ThreesGame::from_seed(4, options);
+        assert_eq!(game.game().board().values().iter().filter(|v| **v != 0).count(), 9);
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        let mut turn = 0;
+        let mut dealt = [0; 4];
+        while game.game().status() == GameStatus::InProgress {
+            turn += 1;
+            let hint = game.next_tile();
+            let before = game.game().board().clone();
+            if let Ok(result) = game.play(dirs[turn % 4]) {
+                // The tile is the one hinted, on the far side from the move
+                let spawn = result.spawned[0];
+                match hint {
+                    NextTile::Tile(value) => {
+                        assert_eq!(spawn.value, value);
+                        dealt[value as usize] += 1;
+                    },
+                    NextTile::Bonus{low, high} => assert!(spawn.value >= low && spawn.value <= high),
+                }
+                let mut slid = before.slide(dirs[turn % 4]).unwrap().0;
+                assert!(entry_cells(&before, &slid, dirs[turn % 4]).contains(&spawn.cell));
+                slid.set(spawn.cell, spawn.value);
+                assert_eq!(&slid, game.game().board());
+            }
+        }
+        assert!(game.game().board().stuck());
+        assert!(game.game().moves() > 20);
+        // The deck deals the three values evenly
+        let total: i32 = dealt.iter().sum();
+        for count in &dealt[1..] {
+            assert!((*count * 3 - total).abs() <= 12 * 3);
+        }
+    }
+
+    #[test]
+    fn deck_with_adversary_and_history() {
+        use crate::adversary::WorstCase;
+        use crate::algorithm::ScoreFunction;
+        use crate::history::HistoryGame;
+        let options = GameOptions::for_merge(MergeRule::Threes);
+        assert_eq!(options.target, 3072);
+        // The adversary picks the cell, but the tile is still the one dealt
+        let adversary = WorstCase{score_fn: ScoreFunction::FreeSpace};
+        let mut game = ThreesGame::from_seed(4, options).with_adversary(Box::new(adversary));
+        let dirs = [MoveDir::Up, MoveDir::Left, MoveDir::Down, MoveDir::Right];
+        for dir in dirs.iter().cycle().take(40) {
+            let hint = game.next_tile();
+            if let (Ok(result), NextTile::Tile(value)) = (game.play(*dir), hint) {
+                assert_eq!(result.spawned[0].value, value);
+            }
+        }
+
+        // A history of a Threes game undoes and redoes its dealt tiles
+        let mut history = HistoryGame::from_game(ThreesGame::from_seed(4, options));
+        let start = history.board().clone();
+        let dir = *dirs.iter().find(|dir| start.is_valid_move(**dir)).unwrap();
+        let dealt = history.play(dir).unwrap().spawned[0];
+        let after = history.board().clone();
+        assert!(history.undo());
+        assert_eq!(history.board(), &start);
+        assert!(history.redo());
+        assert_eq!(history.board(), &after);
+        assert_eq!(history.entries()[1].spawned, vec![dealt]);
+    }
+}